@@ -1,24 +1,203 @@
-use std::io::Error;
+use std::{
+    io::Error,
+    net::{IpAddr, SocketAddr},
+};
 
 mod listen;
 mod primary;
 mod secondary;
 mod trivial;
 
-use crate::{connection::Connection, package_enum, requests::Request, SERVER_PORT};
+use rustls::pki_types::ServerName;
+
+use crate::{
+    connection::{Connection, Protocol},
+    package::Package,
+    package_enum,
+    requests::Request,
+    response::Response,
+    server::{Server, DIRECT_CHANNEL_NAME, GLOBAL_CHANNEL_NAME},
+    tls::{self, Trust},
+    SERVER_PORT,
+};
 
 pub use listen::ListenClient;
 pub use primary::PrimaryClient;
 pub use secondary::SecondaryClient;
 pub use trivial::TrivialClient;
 
-fn server_connection(addr: &str, name: &str) -> Result<Connection, ClientErr> {
-    let mut conn = Connection::to((addr, SERVER_PORT))?;
-    conn.send_package(Request::login(name).package());
-    if conn.wait_package().is_some_and(|p| &p.cmd == "ack") {
-        Ok(conn)
+/// append the default port to a server address, unless it already has one
+///
+/// Accepts host names and IP addresses, both with and without port.
+/// IPv6 addresses with port must be enclosed in brackets, e.g. `[::1]:7000`.
+fn server_addr(addr: &str) -> String {
+    if addr.parse::<SocketAddr>().is_ok() {
+        addr.to_string()
+    } else if let Ok(ip) = addr.trim_matches(['[', ']']).parse::<IpAddr>() {
+        SocketAddr::new(ip, SERVER_PORT).to_string()
+    } else if addr
+        .rsplit_once(':')
+        .is_some_and(|(_, port)| port.parse::<u16>().is_ok())
+    {
+        addr.to_string()
     } else {
-        Err(ClientErr::LoginFailed)
+        format!("{addr}:{SERVER_PORT}")
+    }
+}
+
+/// How to log into a server
+pub enum Credentials {
+    /// log in as guest, without a password
+    Guest,
+    /// log into an existing account
+    Password(String),
+    /// create a new account
+    Register(String),
+}
+
+impl Credentials {
+    fn request(self, name: &str) -> Request {
+        match self {
+            Credentials::Guest => Request::login(name),
+            Credentials::Password(password) => Request::authenticate(name, password),
+            Credentials::Register(password) => Request::register(name, password),
+        }
+    }
+}
+
+/// connect to a server, using TLS if `tls` says whom to trust
+///
+/// The certificate is checked against the host part of `addr`.
+fn connect(addr: &str, tls: Option<&Trust>) -> Result<Connection, ClientErr> {
+    let addr = server_addr(addr);
+    let Some(trust) = tls else {
+        return Ok(Connection::to(addr)?);
+    };
+    let host = addr
+        .rsplit_once(':')
+        .map_or(addr.as_str(), |(host, _)| host);
+    let name = ServerName::try_from(host.trim_matches(['[', ']']).to_string())
+        .map_err(|why| ClientErr::IoError(Error::other(why)))?;
+    Ok(Connection::to_tls(&addr, name, tls::client_config(trust)?)?)
+}
+
+fn server_connection(
+    addr: &str,
+    name: &str,
+    creds: Credentials,
+    tls: Option<&Trust>,
+) -> Result<Connection, ClientErr> {
+    login(connect(addr, tls)?, name, creds)
+}
+
+/// negotiate the protocol and log in over an established connection
+fn login(mut conn: Connection, name: &str, creds: Credentials) -> Result<Connection, ClientErr> {
+    hello(&mut conn)?;
+    conn.send_package(creds.request(name).package());
+    match conn.wait_package().map(Response::try_from) {
+        Some(Ok(Response::Ack)) => Ok(conn),
+        Some(Ok(Response::Err(why))) => Err(ClientErr::LoginFailed(why)),
+        _ => Err(ClientErr::LoginFailed(
+            "no response from server".to_string(),
+        )),
+    }
+}
+
+/// negotiate the protocol version with a server
+///
+/// All features known to this client are offered as capabilities.
+/// Servers rejecting the handshake, e.g. because they don't know it yet,
+/// are spoken to in the legacy protocol.
+fn hello(conn: &mut Connection) -> Result<(), ClientErr> {
+    let args = Protocol {
+        version: Protocol::VERSION,
+        capabilities: Server::FEATURES.map(String::from).to_vec(),
+    }
+    .args();
+    conn.send_package(Request::hello(args).package());
+    match conn.wait_package().map(Response::try_from) {
+        Some(Ok(Response::Info(args))) => {
+            let protocol = Protocol::negotiate(&args, &Server::FEATURES)
+                .map_err(ClientErr::IncompatibleServer)?;
+            if protocol.version > Protocol::VERSION {
+                return Err(ClientErr::IncompatibleServer(format!(
+                    "server chose unknown protocol version {}",
+                    protocol.version
+                )));
+            }
+            conn.set_protocol(protocol);
+            Ok(())
+        }
+        Some(Ok(Response::Err(_))) => {
+            conn.set_protocol(Protocol::default());
+            Ok(())
+        }
+        // e.g. the TLS handshake failed
+        None if !conn.alive() => Err(conn
+            .take_error()
+            .map_or(ClientErr::StartupFailed, ClientErr::IoError)),
+        _ => Err(ClientErr::IncompatibleServer(
+            "no response from server".to_string(),
+        )),
+    }
+}
+
+/// format a message for printing, e.g. `12:34 UTC [sender@channel] message`
+///
+/// `time` is expected in seconds since the unix epoch and displayed in UTC,
+/// labeled as such so it is not mistaken for local time.
+fn format_msg(channel: &str, sender: &str, msg: &str, time: &str) -> String {
+    let time = format_time(time);
+    match channel {
+        GLOBAL_CHANNEL_NAME => format!("{time}[{sender}] {msg}"),
+        DIRECT_CHANNEL_NAME => format!("{time}[{sender} -> you] {msg}"),
+        _ => format!("{time}[{sender}@{channel}] {msg}"),
+    }
+}
+
+/// format seconds since the unix epoch as `hh:mm UTC `, or nothing if invalid
+fn format_time(time: &str) -> String {
+    match time.parse::<u64>() {
+        Ok(secs) => format!("{:02}:{:02} UTC ", secs / 3600 % 24, secs / 60 % 60),
+        Err(_) => String::new(),
+    }
+}
+
+/// format an asynchronous response for printing
+///
+/// Messages from legacy servers, which come without id and time, are accepted as well.
+fn format_event(mut pkg: Package) -> Option<String> {
+    if pkg.cmd == "msg" && pkg.args.len() == 3 {
+        pkg.args.resize(5, String::new());
+    }
+    Some(match Response::try_from(pkg).ok()? {
+        Response::Msg(channel, sender, msg, _, time) => format_msg(&channel, &sender, &msg, &time),
+        Response::Topic(channel, name, topic) => {
+            format!(
+                "*** {name} set the topic of {} to: {topic}",
+                channel_name(&channel)
+            )
+        }
+        Response::Member(channel, name, event) => {
+            let channel = channel_name(&channel);
+            match event.as_str() {
+                "join" => format!("--> {name} joined {channel}"),
+                "leave" => format!("<-- {name} left {channel}"),
+                "kick" => format!("<-- {name} was kicked from {channel}"),
+                "ban" => format!("<-- {name} was banned from {channel}"),
+                _ => format!("--- {name} in {channel}: {event}"),
+            }
+        }
+        Response::Shutdown(why) => format!("*** server shut down: {why}"),
+        _ => return None,
+    })
+}
+
+fn channel_name(chan: &str) -> &str {
+    if chan.is_empty() {
+        "<GLOBAL>"
+    } else {
+        chan
     }
 }
 
@@ -26,7 +205,9 @@ fn server_connection(addr: &str, name: &str) -> Result<Connection, ClientErr> {
 pub enum ClientErr {
     IoError(Error),
     NonBlockingFailed,
-    LoginFailed,
+    LoginFailed(String),
+    /// the server doesn't speak a compatible protocol version
+    IncompatibleServer(String),
     StartupFailed,
 }
 
@@ -40,7 +221,8 @@ package_enum! {
     /// Communication between primary and secondary clients.
     ///
     /// Two groups exist:
-    /// * metadata: updates information about name, joined channels and blocked users
+    /// * metadata: updates information about name, joined channels, blocked users
+    /// and the protocol negotiated with the server
     /// * quit: signals the primary client to stop running
     ///
     /// To distinguish between regular packages sent between client and server and
@@ -49,6 +231,93 @@ package_enum! {
         Name(":name" => name),
         Channels(":channels" => [channels]),
         Blocked(":blocked" => [blocked]),
+        Protocol(":protocol" => [args]),
         Quit(":quit"),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use super::{format_event, format_msg, login, server_addr, Credentials};
+    use crate::{
+        connection::Protocol, Connection, ListenClient, MemoryStream, Package, PrimaryClient,
+        Request, Response, Server, ServerConfig, TrivialClient,
+    };
+
+    #[test]
+    fn legacy_server() {
+        let (local, remote) = MemoryStream::pair();
+        // a server from before the handshake
+        let server = thread::spawn(move || {
+            let mut conn = Connection::new(remote);
+            let hello = conn.wait_package().unwrap();
+            assert_eq!(hello.cmd, "hello");
+            conn.send_package(Response::err("unknown command hello").package());
+            let login = Request::try_from(conn.wait_package().unwrap()).unwrap();
+            assert!(matches!(login, Request::Login(name) if name == "alice"));
+            conn.send_package(Response::Ack.package());
+            conn
+        });
+        let conn = login(
+            Connection::new(Box::new(local)),
+            "alice",
+            Credentials::Guest,
+        )
+        .unwrap();
+        assert_eq!(conn.protocol().version, Protocol::LEGACY_VERSION);
+        server.join().unwrap();
+        let legacy_msg = Response::msg("", "bob", "hi", "", "").package();
+        let legacy_msg = Package {
+            args: legacy_msg.args[..3].to_vec(),
+            ..legacy_msg
+        };
+        assert_eq!(format_event(legacy_msg).unwrap(), "[bob] hi");
+    }
+
+    #[test]
+    fn memory_transport() {
+        let mut server = Server::builder()
+            .config(ServerConfig {
+                accounts: None,
+                ..Default::default()
+            })
+            .without_listeners()
+            .build()
+            .unwrap();
+        let mut connect = || {
+            let (local, remote) = MemoryStream::pair();
+            server.accept(remote).unwrap();
+            Box::new(local)
+        };
+        let (primary, trivial, listen) = (connect(), connect(), connect());
+        let handle = server.handle();
+        let thread = thread::spawn(move || server.run());
+        PrimaryClient::with_transport(primary, "alice", Credentials::Guest).unwrap();
+        TrivialClient::with_transport(trivial, "bob", Credentials::Guest).unwrap();
+        ListenClient::with_transport(listen).unwrap();
+        handle.stop();
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn message_format() {
+        assert_eq!(format_msg("", "me", "hi", "45296"), "12:34 UTC [me] hi");
+        assert_eq!(format_msg("__direct", "me", "hi", ""), "[me -> you] hi");
+        assert_eq!(
+            format_msg("chan", "me", "hi", "86399"),
+            "23:59 UTC [me@chan] hi"
+        );
+    }
+
+    #[test]
+    fn server_addresses() {
+        assert_eq!(server_addr("localhost"), "localhost:6447");
+        assert_eq!(server_addr("localhost:7000"), "localhost:7000");
+        assert_eq!(server_addr("10.0.0.1"), "10.0.0.1:6447");
+        assert_eq!(server_addr("::1"), "[::1]:6447");
+        assert_eq!(server_addr("[::1]"), "[::1]:6447");
+        assert_eq!(server_addr("[::1]:7000"), "[::1]:7000");
+    }
+}
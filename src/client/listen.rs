@@ -1,15 +1,26 @@
 use std::io::{stdout, Write};
 
 use super::ClientErr;
-use crate::{connection::Connection, requests::Request, response::Response, SERVER_PORT};
+use crate::{
+    connection::Connection, requests::Request, response::Response, tls::Trust, transport::Transport,
+};
 
 pub struct ListenClient {
     conn: Connection,
 }
 
 impl ListenClient {
-    pub fn connect(addr: &str) -> Result<Self, ClientErr> {
-        let mut conn = Connection::to((addr, SERVER_PORT))?;
+    pub fn connect(addr: &str, tls: Option<&Trust>) -> Result<Self, ClientErr> {
+        Self::start(super::connect(addr, tls)?)
+    }
+
+    /// listen over any transport, e.g. a [`MemoryStream`](crate::MemoryStream)
+    pub fn with_transport(transport: Box<dyn Transport>) -> Result<Self, ClientErr> {
+        Self::start(Connection::new(transport))
+    }
+
+    fn start(mut conn: Connection) -> Result<Self, ClientErr> {
+        super::hello(&mut conn)?;
         conn.send_package(Request::Listen.package());
         if !matches!(
             conn.wait_package().map(|p| p.try_into()),
@@ -24,10 +35,8 @@ impl ListenClient {
 
     pub fn run(&mut self) {
         while self.conn.alive() {
-            if let Some(Ok(Response::Msg(_, name, msg))) =
-                self.conn.wait_package().map(|p| p.try_into())
-            {
-                println!("[{name}] {msg}");
+            if let Some(line) = self.conn.wait_package().and_then(super::format_event) {
+                println!("{line}");
                 stdout().flush().unwrap();
             }
         }
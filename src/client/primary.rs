@@ -1,34 +1,68 @@
-use std::{net::TcpListener, thread, time::Duration};
+use std::net::SocketAddr;
+
+use mio::{net::TcpListener, Events, Interest, Poll, Token};
 
 use crate::{
-    connection::Connection,
+    connection::{poll_events, Connection},
     package::Package,
     response::Response,
-    server::{DIRECT_CHANNEL_NAME, GLOBAL_CHANNEL_NAME},
+    tls::Trust,
+    transport::Transport,
 };
 
-use super::{ClientErr, InterClientComm};
+use super::{ClientErr, Credentials, InterClientComm};
+
+const SERVER: Token = Token(0);
+const LISTENER: Token = Token(1);
+const SECONDARY: Token = Token(2);
 
 pub struct PrimaryClient {
     server: Connection,
     listener: TcpListener,
     secondary: Option<Connection>,
+    poll: Poll,
     name: String,
     channels: Vec<String>,
     blocked: Vec<String>,
 }
 
 impl PrimaryClient {
-    pub fn connect(addr: &str, name: &str) -> Result<Self, ClientErr> {
-        let server = super::server_connection(addr, name)?;
-        let listener = TcpListener::bind("127.0.0.1:0")?;
-        listener.set_nonblocking(true)?;
+    pub fn connect(
+        addr: &str,
+        name: &str,
+        creds: Credentials,
+        tls: Option<&Trust>,
+    ) -> Result<Self, ClientErr> {
+        Self::start(super::server_connection(addr, name, creds, tls)?, name)
+    }
+
+    /// log in over any transport, e.g. a [`MemoryStream`](crate::MemoryStream)
+    ///
+    /// Secondary clients still connect through a local TCP port.
+    pub fn with_transport(
+        transport: Box<dyn Transport>,
+        name: &str,
+        creds: Credentials,
+    ) -> Result<Self, ClientErr> {
+        Self::start(super::login(Connection::new(transport), name, creds)?, name)
+    }
+
+    fn start(mut server: Connection, name: &str) -> Result<Self, ClientErr> {
+        let mut listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))?;
+        let poll = Poll::new()?;
+        server.register(poll.registry(), SERVER)?;
+        poll.registry()
+            .register(&mut listener, LISTENER, Interest::READABLE)?;
         let local_port = listener.local_addr()?.port();
-        println!("rs_chat primary client v{} running on port {local_port}", env!("CARGO_PKG_VERSION"));
+        println!(
+            "rs_chat primary client v{} running on port {local_port}",
+            env!("CARGO_PKG_VERSION")
+        );
         Ok(Self {
             server,
             listener,
             secondary: None,
+            poll,
             name: name.to_string(),
             channels: vec![String::new()],
             blocked: Vec::new(),
@@ -36,16 +70,28 @@ impl PrimaryClient {
     }
 
     pub fn run(&mut self) {
+        let mut events = Events::with_capacity(16);
+        let mut shut_down = false;
         loop {
-            if let Some(incoming) = self.server.get_package() {
-                if &incoming.cmd == "msg" {
-                    Self::print_message(incoming);
+            self.server.flush();
+            while let Some(incoming) = self.server.get_package() {
+                if Response::ASYNC.contains(&incoming.cmd.as_str()) {
+                    if incoming.cmd == "shutdown" {
+                        shut_down = true;
+                        // the secondary tells the user as well
+                        if let Some(conn) = &mut self.secondary {
+                            conn.send_package(&incoming);
+                        }
+                    }
+                    self.check_removed(&incoming);
+                    Self::print_event(incoming);
                 } else if let Some(conn) = &mut self.secondary {
                     conn.send_package(incoming);
                 }
             }
             if let Some(conn) = &mut self.secondary {
-                if let Some(outgoing) = conn.get_package() {
+                conn.flush();
+                while let Some(outgoing) = conn.get_package() {
                     if outgoing.cmd.starts_with(':') {
                         match outgoing.try_into() {
                             Ok(InterClientComm::Channels(channels)) => self.channels = channels,
@@ -63,35 +109,56 @@ impl PrimaryClient {
                 if !conn.alive() {
                     self.secondary.take();
                 }
-            } else if let Ok((stream, _)) = self.listener.accept() {
-                if let Ok(mut conn) = Connection::new(stream) {
-                    conn.send_package(InterClientComm::Name(self.name.clone()).package());
-                    conn.send_package(InterClientComm::Channels(self.channels.clone()).package());
-                    conn.send_package(InterClientComm::Blocked(self.blocked.clone()).package());
-                    self.secondary = Some(conn);
+            }
+            // only one secondary at a time, others wait in the backlog
+            if self.secondary.is_none() {
+                if let Ok((stream, _)) = self.listener.accept() {
+                    let mut conn: Connection = Connection::new(Box::new(stream));
+                    if conn.register(self.poll.registry(), SECONDARY).is_ok() {
+                        conn.send_package(InterClientComm::Name(self.name.clone()).package());
+                        conn.send_package(
+                            InterClientComm::Channels(self.channels.clone()).package(),
+                        );
+                        conn.send_package(InterClientComm::Blocked(self.blocked.clone()).package());
+                        conn.send_package(
+                            InterClientComm::Protocol(self.server.protocol().args()).package(),
+                        );
+                        self.secondary = Some(conn);
+                    }
                 }
             }
             if !self.server.alive() {
-                eprintln!("disconnected from server");
+                if !shut_down {
+                    eprintln!("disconnected from server");
+                }
+                return;
+            }
+            if let Err(why) = poll_events(&mut self.poll, &mut events, None) {
+                eprintln!("failed to wait for events: {why}");
                 return;
             }
-            thread::sleep(Duration::from_millis(20));
         }
     }
 
-    fn print_message(msg: Package) {
-        let Ok(Response::Msg(channel, mut sender, msg)) = msg.try_into() else {
-            eprintln!("server sent invalid response");
+    /// forget a channel the user was kicked or banned from, or that was closed
+    fn check_removed(&mut self, event: &Package) {
+        let Ok(Response::Member(channel, name, event)) = event.clone().try_into() else {
             return;
         };
-        let ch = match channel.as_str() {
-            GLOBAL_CHANNEL_NAME => GLOBAL_CHANNEL_NAME,
-            DIRECT_CHANNEL_NAME => " -> you",
-            _ => {
-                sender.push('@');
-                &channel
-            }
+        if name != self.name || !["kick", "ban"].contains(&event.as_str()) {
+            return;
+        }
+        self.channels.retain(|c| *c != channel);
+        if let Some(conn) = &mut self.secondary {
+            conn.send_package(InterClientComm::Channels(self.channels.clone()).package());
+        }
+    }
+
+    fn print_event(event: Package) {
+        let Some(line) = super::format_event(event) else {
+            eprintln!("server sent invalid response");
+            return;
         };
-        println!("[{sender}{ch}] {msg}");
+        println!("{line}");
     }
 }
@@ -5,11 +5,13 @@ use std::{
 };
 
 use crate::{
-    connection::Connection, package::PackageParseError, requests::Request,
+    connection::Connection,
+    package::{Package, PackageParseError},
+    requests::Request,
     response::Response,
 };
 
-use super::{ClientErr, InterClientComm};
+use super::{channel_name, ClientErr, InterClientComm};
 
 const START_MESSAGE: &str = concat!(
     "rs_chat secondary client v",
@@ -28,10 +30,16 @@ Commands:
  :s               get server information
  :q               quit this program
  :w [<chan>]      get name list
+ :h [<n>] [<chan>] message history
  :c [<arg..>]     channel operations
+ :m <chan> <op..> moderate channel
+ :t <chan> [<t>]  get or set channel topic
  :b [<name>]      block / unblock player
  :o               get your offenses
- :p <name>        pardon player";
+ :p <name>        pardon player
+ :a <op..>        administrate server";
+
+const DEFAULT_HISTORY: usize = 10;
 
 const HELP_HELP: &str = "? - print help
 Use ?<cmd> to get details about other commands";
@@ -57,6 +65,11 @@ Usage: :w [<channel>]
 Print a list of names that are subscribed to a channel. If no channel is provided, the global channel is taken.
 Note: other clients may unsubscribe from the global channel and thus stay anonymous";
 
+const HELP_HISTORY: &str = ":h - show message history
+Usage: :h [<count>] [<channel>]
+Print the last <count> messages (default 10) of a channel. If no channel is provided, the global channel is taken.
+Note: you must first join a channel via ':c' to view its history";
+
 const HELP_CHANNEL: &str = ":c - channel operations
 Several actions can be performed:
  :c - list available channels. Channels you have joined will be marked with (*).
@@ -65,6 +78,26 @@ If a channel named <channel> already exists, attempts to join that channel with
 Otherwise, the channel is created. If no password is given, an empty password is used.
  :c -[<channel>] - leave a channel. Omit the channel to leave the global channel.";
 
+const HELP_MODERATE: &str = ":m - moderate a channel
+Usage: :m <channel> <action> [<arg>]
+Available actions:
+ kick <name> - remove a member from the channel
+ ban <name> - kick a user and prevent them from joining again
+ unban <name> - allow a banned user to join again
+ op <name> - make a member an operator
+ deop <name> - revoke operator privileges (founder only)
+ founder <name> - make a member the founder, you become an operator (founder only)
+ pw [<password>] - change the password. Omit the password to allow anyone to join.
+ slow <seconds> - make members wait between their posts. Use 0 to turn it off.
+Note: only the founder and operators of a channel may moderate it";
+
+const HELP_TOPIC: &str = ":t - channel topic
+Usage: :t <channel> [<topic>]
+Prints the topic of a channel. If a topic is given, it is set as the new topic instead.
+Use '-' as channel name for the global channel.
+Note: only operators and admins may change the topic of a channel,
+and only admins that of the global channel";
+
 const HELP_BLOCK: &str = ":b - (un)block a user
 Usage: :b [<name>]
 Blocks selected user. Enter again to unblock.
@@ -78,8 +111,19 @@ const HELP_PARDON: &str = ":p - pardon another player
 Usage: :p <name>
 Reduces the number of offenses of that player by 1.
 If this player did not have any offenses, it will be counted as an offense by you.
+You can't pardon yourself, and only a few other players within a while.
 A name check analog to direct messages will be performed.";
 
+const HELP_ADMIN: &str = ":a - administrate the server
+Usage: :a <action> <args..>
+Available actions:
+ kick <name> - disconnect a user
+ ban <name or ip> <secs> - disconnect matching users and prevent them from logging in
+ mute <name> <secs> - prevent a user from writing anything
+ pardons - review who pardoned whom recently
+Use 0 seconds to lift a ban or mute.
+Note: only admins configured on the server may do this";
+
 /// Things a user might want to do
 ///
 /// In most cases, this translates to one [`Request`].
@@ -92,13 +136,17 @@ enum UserCmd {
     ServerInfo,
     Quit(bool),
     Who(String),
+    History(String, usize),
     ChannelList,
     ChannelJoinNew(String, String),
     ChannelLeave(String),
+    Topic(String, Option<String>),
+    Moderate(Request),
     BlockList,
     Block(String),
     Offenses,
     Pardon(String),
+    PardonLog,
     /// top secret, don't tell anybody
     SecretHelp,
 }
@@ -113,6 +161,8 @@ enum Happenings {
     OwnMistake(String),
     ProtocolViolation,
     ServerDied,
+    /// the server stopped, for the given reason
+    ServerShutdown(String),
     QuitCmd,
 }
 
@@ -132,6 +182,8 @@ pub struct SecondaryClient {
     name: String,
     channels: Vec<String>,
     blocked: Vec<String>,
+    /// tag of the last request, if the server supports tags
+    last_tag: Option<u64>,
 }
 
 impl SecondaryClient {
@@ -162,16 +214,26 @@ impl SecondaryClient {
         else {
             return Err(ClientErr::StartupFailed);
         };
+        let Ok(InterClientComm::Protocol(protocol)) = conn
+            .wait_package()
+            .ok_or(ClientErr::StartupFailed)?
+            .try_into()
+        else {
+            return Err(ClientErr::StartupFailed);
+        };
+        // the first argument is the version
+        let tags = protocol.iter().skip(1).any(|c| c == "tags");
         Ok(Self {
             conn,
             name,
             channels,
             blocked,
+            last_tag: tags.then_some(0),
         })
     }
 
     /// run the client
-    /// 
+    ///
     /// this function will only return when either an unrecoverable error has occured
     /// or the user decided to quit (see [`Happenings`])
     pub fn run(&mut self) {
@@ -179,13 +241,14 @@ impl SecondaryClient {
         loop {
             let inp = get_line("> ");
             if let Some(cmd) = Self::parse_input(inp.trim()) {
-                if let Err(why) = self.exec_cmd(cmd) {
+                if let Err(why) = self.sync().and_then(|_| self.exec_cmd(cmd)) {
                     match why {
                         Happenings::ResponseErr(err) => {
                             eprintln!("server sent invalid response: {err}")
                         }
                         Happenings::ProtocolViolation => eprintln!("server violated the protocol"),
                         Happenings::ServerDied => eprintln!("server died. oh no."),
+                        Happenings::ServerShutdown(why) => eprintln!("server shut down: {why}"),
                         Happenings::QuitCmd => {}
                         Happenings::OwnMistake(what) => {
                             eprintln!("you made a mistake (or me?): {what}");
@@ -234,6 +297,13 @@ impl SecondaryClient {
             "q" => UserCmd::Quit(false),
             "q!" => UserCmd::Quit(true),
             "w" => UserCmd::Who(args.first().map(|a| a.to_string()).unwrap_or_default()),
+            "h" => {
+                let (count, chan) = match args.first().map(|a| a.parse()) {
+                    Some(Ok(count)) => (count, args.get(1)),
+                    _ => (DEFAULT_HISTORY, args.first()),
+                };
+                UserCmd::History(chan.map(|c| c.to_string()).unwrap_or_default(), count)
+            }
             "c" => {
                 if let Some(channel) = args.first() {
                     if let Some(channel) = channel.strip_prefix('-') {
@@ -249,6 +319,52 @@ impl SecondaryClient {
                     UserCmd::ChannelList
                 }
             }
+            "m" => {
+                let (Some(chan), Some(action)) = (args.first(), args.get(1)) else {
+                    eprintln!("please provide channel and action");
+                    return None;
+                };
+                let arg = args.get(2).map(|a| a.to_string());
+                UserCmd::Moderate(match (*action, arg) {
+                    ("pw", pw) => Request::channel_password(*chan, pw.unwrap_or_default()),
+                    ("kick", Some(name)) => Request::kick(*chan, name),
+                    ("ban", Some(name)) => Request::ban(*chan, name),
+                    ("unban", Some(name)) => Request::unban(*chan, name),
+                    ("op", Some(name)) => Request::promote(*chan, name),
+                    ("deop", Some(name)) => Request::demote(*chan, name),
+                    ("founder", Some(name)) => Request::handover(*chan, name),
+                    ("slow", Some(secs)) => Request::slow_mode(*chan, secs),
+                    ("kick" | "ban" | "unban" | "op" | "deop" | "founder", None) => {
+                        eprintln!("please provide a name");
+                        return None;
+                    }
+                    ("slow", None) => {
+                        eprintln!("please provide the number of seconds");
+                        return None;
+                    }
+                    _ => {
+                        eprintln!("unknown action {action}");
+                        return None;
+                    }
+                })
+            }
+            "t" => {
+                let Some((chan, topic)) = inp
+                    .split_once(char::is_whitespace)
+                    .map(|(_, args)| args.trim_start())
+                    .and_then(|args| {
+                        let (chan, topic) =
+                            args.split_once(char::is_whitespace).unwrap_or((args, ""));
+                        (!chan.is_empty()).then_some((chan, topic.trim()))
+                    })
+                else {
+                    eprintln!("please provide a channel");
+                    return None;
+                };
+                let chan = if chan == "-" { "" } else { chan };
+                let topic = (!topic.is_empty()).then(|| topic.to_string());
+                UserCmd::Topic(chan.to_string(), topic)
+            }
             "b" => {
                 if let Some(name) = args.first() {
                     UserCmd::Block(name.to_string())
@@ -265,6 +381,28 @@ impl SecondaryClient {
                     return None;
                 }
             }
+            "a" => {
+                let Some(action) = args.first() else {
+                    eprintln!("please provide an action");
+                    return None;
+                };
+                if *action == "pardons" {
+                    return Some(UserCmd::PardonLog);
+                }
+                UserCmd::Moderate(match (*action, args.get(1), args.get(2)) {
+                    ("kick", Some(name), _) => Request::server_kick(*name),
+                    ("ban", Some(target), Some(secs)) => Request::server_ban(*target, *secs),
+                    ("mute", Some(name), Some(secs)) => Request::mute(*name, *secs),
+                    ("kick" | "ban" | "mute", ..) => {
+                        eprintln!("missing arguments, see ?a");
+                        return None;
+                    }
+                    _ => {
+                        eprintln!("unknown action {action}");
+                        return None;
+                    }
+                })
+            }
             "?!" => UserCmd::SecretHelp,
             _ => {
                 eprintln!("unknown command {cmd}");
@@ -284,10 +422,14 @@ impl SecondaryClient {
                     Some('s') => HELP_SERVER,
                     Some('q') => HELP_QUIT,
                     Some('w') => HELP_WHO,
+                    Some('h') => HELP_HISTORY,
                     Some('c') => HELP_CHANNEL,
+                    Some('m') => HELP_MODERATE,
+                    Some('t') => HELP_TOPIC,
                     Some('b') => HELP_BLOCK,
                     Some('o') => HELP_OFFENSES,
                     Some('p') => HELP_PARDON,
+                    Some('a') => HELP_ADMIN,
                     Some(c) => {
                         eprintln!("unknown command {c}");
                         return Ok(());
@@ -306,14 +448,32 @@ impl SecondaryClient {
                 return Err(Happenings::QuitCmd);
             }
             UserCmd::ChannelList => {
-                let mut disp = String::from("channels: ");
-                for chan in self.info_request(Request::ListChannels)? {
-                    if self.channels.contains(&chan) {
-                        disp += "(*) ";
+                println!("channels:");
+                for chan in self.info_request(Request::ListChannels)?.chunks(2) {
+                    let [chan, topic] = chan else {
+                        return Err(Happenings::ProtocolViolation);
+                    };
+                    let joined = if self.channels.contains(chan) {
+                        "(*) "
+                    } else {
+                        ""
+                    };
+                    if topic.is_empty() {
+                        println!(" {joined}{}", channel_name(chan));
+                    } else {
+                        println!(" {joined}{} - {topic}", channel_name(chan));
                     }
-                    disp = disp + channel_name(&chan) + ", ";
                 }
-                println!("{}", &disp[..disp.len() - 2]);
+            }
+            UserCmd::Topic(chan, None) => {
+                let topic = self.info_request(Request::Topic(chan.clone()))?;
+                match topic.first().filter(|t| !t.is_empty()) {
+                    Some(topic) => println!("topic of {}: {topic}", channel_name(&chan)),
+                    None => println!("{} has no topic", channel_name(&chan)),
+                }
+            }
+            UserCmd::Topic(chan, Some(topic)) => {
+                self.ack_request(Request::SetTopic(chan, topic))?;
             }
             UserCmd::ServerInfo => {
                 println!("server: {}", Disp(&self.info_request(Request::About)?));
@@ -342,6 +502,23 @@ impl SecondaryClient {
                     eprintln!("join channel {} to list its members", channel_name(&chan));
                 }
             }
+            UserCmd::History(chan, count) => {
+                if self.channels.contains(&chan) {
+                    let history =
+                        self.info_request(Request::History(chan.clone(), count.to_string()))?;
+                    if history.is_empty() {
+                        println!("no messages in {}", channel_name(&chan));
+                    }
+                    for msg in history.chunks(4) {
+                        let [_, time, sender, msg] = msg else {
+                            return Err(Happenings::ProtocolViolation);
+                        };
+                        println!("{}", super::format_msg(&chan, sender, msg, time));
+                    }
+                } else {
+                    eprintln!("join channel {} to view its history", channel_name(&chan));
+                }
+            }
             UserCmd::ChannelMsg(chan, msg) => {
                 if self.channels.contains(&chan) {
                     self.ack_request(Request::Post(chan, msg))?;
@@ -383,6 +560,22 @@ impl SecondaryClient {
                     println!("pardoned {name}")
                 }
             }
+            UserCmd::PardonLog => {
+                let log = self.info_request(Request::PardonLog)?;
+                if log.is_empty() {
+                    println!("nobody was pardoned recently");
+                }
+                for pardon in log.chunks(3) {
+                    let [time, by, of] = pardon else {
+                        return Err(Happenings::ProtocolViolation);
+                    };
+                    println!("{}{by} pardoned {of}", super::format_time(time));
+                }
+            }
+            UserCmd::Moderate(req) => {
+                self.ack_request(req)?;
+                println!("done");
+            }
             UserCmd::ChannelLeave(channel) => {
                 if self.channels.contains(&channel) {
                     self.ack_request(Request::Unsubscribe(channel.clone()))?;
@@ -398,7 +591,9 @@ impl SecondaryClient {
                 if self.channels.contains(&channel) {
                     eprintln!("you are already in {}", channel_name(&channel))
                 } else {
-                    if self.info_request(Request::ListChannels)?.contains(&channel) {
+                    let channels = self.info_request(Request::ListChannels)?;
+                    // every other argument is a topic
+                    if channels.iter().step_by(2).any(|c| *c == channel) {
                         self.ack_request(Request::Subscribe(channel.clone(), passwd))?;
                         println!("joined {}", channel_name(&channel));
                     } else {
@@ -406,9 +601,9 @@ impl SecondaryClient {
                         println!("created {}", channel_name(&channel));
                     }
                     self.channels.push(channel);
-                    self.conn.send_package(InterClientComm::Channels(self.channels.clone()).package());
+                    self.conn
+                        .send_package(InterClientComm::Channels(self.channels.clone()).package());
                 }
-                
             }
         }
         Ok(())
@@ -456,9 +651,7 @@ impl SecondaryClient {
     }
 
     fn info_request(&mut self, req: Request) -> Result<Vec<String>, Happenings> {
-        self.conn.send_package(req.package());
-        let pkg = self.conn.wait_package().ok_or(Happenings::ServerDied)?;
-        match pkg.try_into()? {
+        match self.request(req)? {
             Response::Info(data) => Ok(data),
             Response::Err(why) => Err(Happenings::OwnMistake(why)),
             _ => Err(Happenings::ProtocolViolation),
@@ -466,14 +659,63 @@ impl SecondaryClient {
     }
 
     fn ack_request(&mut self, req: Request) -> Result<(), Happenings> {
-        self.conn.send_package(req.package());
-        let pkg = self.conn.wait_package().ok_or(Happenings::ServerDied)?;
-        match pkg.try_into()? {
+        match self.request(req)? {
             Response::Ack => Ok(()),
             Response::Err(why) => Err(Happenings::OwnMistake(why)),
             _ => Err(Happenings::ProtocolViolation),
         }
     }
+
+    /// send a request and wait for its response
+    ///
+    /// If the server supports tags, every request gets a new one
+    /// and a response with any other tag violates the protocol.
+    fn request(&mut self, req: Request) -> Result<Response, Happenings> {
+        let tag = self.last_tag.as_mut().map(|t| {
+            *t += 1;
+            t.to_string()
+        });
+        self.conn.send_package(req.package().with_tag(tag.clone()));
+        loop {
+            let pkg = self.conn.wait_package().ok_or(Happenings::ServerDied)?;
+            // the shutdown notice is never tagged
+            if pkg.cmd == "shutdown" || pkg.cmd.starts_with(':') {
+                self.unrequested(pkg)?;
+            } else if pkg.tag != tag {
+                return Err(Happenings::ProtocolViolation);
+            } else {
+                return Ok(pkg.try_into()?);
+            }
+        }
+    }
+
+    /// handle everything the primary client sent on its own
+    fn sync(&mut self) -> Result<(), Happenings> {
+        while let Some(pkg) = self.conn.get_package() {
+            self.unrequested(pkg)?;
+        }
+        Ok(())
+    }
+
+    /// handle a package that isn't a response to a request
+    ///
+    /// The primary client updates the joined channels when
+    /// the user is removed from one, and forwards the shutdown notice.
+    fn unrequested(&mut self, pkg: Package) -> Result<(), Happenings> {
+        if pkg.cmd == "shutdown" {
+            return match pkg.try_into()? {
+                Response::Shutdown(why) => Err(Happenings::ServerShutdown(why)),
+                _ => Err(Happenings::ProtocolViolation),
+            };
+        }
+        match pkg.try_into() {
+            Ok(InterClientComm::Channels(channels)) => {
+                self.channels = channels;
+                Ok(())
+            }
+            _ => Err(Happenings::ProtocolViolation),
+        }
+    }
 }
 
 struct Disp<'d>(&'d [String]);
@@ -502,11 +744,3 @@ fn get_line(prompt: &str) -> String {
         .expect("stdin should be available for cli");
     answer
 }
-
-fn channel_name(chan: &str) -> &str {
-    if chan.is_empty() {
-        "<GLOBAL>"
-    } else {
-        chan
-    }
-}
@@ -2,47 +2,94 @@ use std::{
     io::stdin,
     sync::mpsc::{channel, Sender},
     thread,
-    time::Duration,
 };
 
-use crate::{connection::Connection, package::Package};
+use mio::{Events, Poll, Token, Waker};
 
-use super::{server_connection, ClientErr};
+use crate::{
+    connection::{poll_events, Connection},
+    package::Package,
+    response::Response,
+    tls::Trust,
+    transport::Transport,
+};
+
+use super::{login, server_connection, ClientErr, Credentials};
+
+const SERVER: Token = Token(0);
+const INPUT: Token = Token(1);
 
 pub struct TrivialClient {
     conn: Connection,
+    poll: Poll,
 }
 
 impl TrivialClient {
-    pub fn connect(addr: &str, name: &str) -> Result<Self, ClientErr> {
-        Ok(Self {
-            conn: server_connection(addr, name)?,
-        })
+    pub fn connect(
+        addr: &str,
+        name: &str,
+        creds: Credentials,
+        tls: Option<&Trust>,
+    ) -> Result<Self, ClientErr> {
+        Self::start(server_connection(addr, name, creds, tls)?)
+    }
+
+    /// log in over any transport, e.g. a [`MemoryStream`](crate::MemoryStream)
+    pub fn with_transport(
+        transport: Box<dyn Transport>,
+        name: &str,
+        creds: Credentials,
+    ) -> Result<Self, ClientErr> {
+        Self::start(login(Connection::new(transport), name, creds)?)
+    }
+
+    fn start(mut conn: Connection) -> Result<Self, ClientErr> {
+        let poll = Poll::new()?;
+        conn.register(poll.registry(), SERVER)?;
+        Ok(Self { conn, poll })
     }
 
     pub fn run(&mut self) {
         println!("rs_chat trivial client v{}", env!("CARGO_PKG_VERSION"));
         let (tx, rx) = channel();
-        input_thread(tx);
+        let waker = match Waker::new(self.poll.registry(), INPUT) {
+            Ok(waker) => waker,
+            Err(why) => {
+                eprintln!("failed to read input: {why}");
+                return;
+            }
+        };
+        input_thread(tx, waker);
+        let mut events = Events::with_capacity(4);
         loop {
-            if let Ok(pkg) = rx.try_recv() {
+            self.conn.flush();
+            while let Ok(pkg) = rx.try_recv() {
                 self.conn.send_package(pkg);
             }
-            self.conn.get_package();
+            while let Some(pkg) = self.conn.get_package() {
+                if let Ok(Response::Shutdown(why)) = pkg.try_into() {
+                    println!("server shut down: {why}");
+                    return;
+                }
+            }
             if !self.conn.alive() {
                 println!("connection was lost");
                 return;
             }
-            thread::sleep(Duration::from_millis(25));
+            if let Err(why) = poll_events(&mut self.poll, &mut events, None) {
+                eprintln!("failed to wait for events: {why}");
+                return;
+            }
         }
     }
 }
 
-fn input_thread(tx: Sender<Package>) {
+fn input_thread(tx: Sender<Package>, waker: Waker) {
     thread::spawn(move || loop {
         let mut inp = String::new();
         stdin().read_line(&mut inp).unwrap();
-        tx.send(inp_to_package(inp.trim_end())).unwrap()
+        tx.send(inp_to_package(inp.trim_end())).unwrap();
+        waker.wake().unwrap();
     });
 }
 
@@ -51,11 +98,13 @@ fn inp_to_package(inp: &str) -> Package {
         return Package {
             cmd: inp.to_string(),
             args: Vec::new(),
+            tag: None,
         };
     };
     Package {
         cmd: cmd.to_string(),
         args: lex_args(args),
+        tag: None,
     }
 }
 
@@ -1,95 +1,665 @@
 use std::{
     borrow::Borrow,
-    io::{Error, ErrorKind, Read as _, Write as _},
-    net::{TcpStream, ToSocketAddrs},
-    thread,
+    collections::VecDeque,
+    io::{Error, ErrorKind, Read, Write},
+    net::ToSocketAddrs,
+    sync::Arc,
     time::Duration,
 };
 
-use crate::package::Package;
+use mio::{net::TcpStream, Events, Interest, Poll, Registry, Token};
+use rustls::{
+    pki_types::ServerName, ClientConfig, ClientConnection, ServerConfig, ServerConnection,
+};
+
+use crate::{
+    package::{Package, PackageParseError},
+    transport::Transport,
+};
 
 const BUF_SIZE: usize = 256;
+/// connections are closed once an oversized package
+/// exceeds the size limit by this factor
+const GROSS_VIOLATION: usize = 16;
+
+/// Bounds for incoming packages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// maximum size of an encoded package in bytes
+    pub max_len: usize,
+    /// maximum number of arguments of a package
+    pub max_args: usize,
+}
+
+impl Limits {
+    pub const UNLIMITED: Self = Self {
+        max_len: usize::MAX,
+        max_args: usize::MAX,
+    };
+}
+
+/// The socket of a [`Connection`], optionally encrypted
+///
+/// Both variants are nonblocking. Reading and writing a TLS stream
+/// also drives the handshake, so it needs no special treatment.
+enum Stream<T> {
+    Plain(T),
+    Tls(Box<rustls::Connection>, T),
+}
 
-pub struct Connection {
-    stream: TcpStream,
+impl<T: Transport> Stream<T> {
+    fn socket(&mut self) -> &mut T {
+        match self {
+            Stream::Plain(socket) | Stream::Tls(_, socket) => socket,
+        }
+    }
+}
+
+impl<T: Transport> Read for Stream<T> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let (tls, socket) = match self {
+            Stream::Plain(socket) => return socket.read(buf),
+            Stream::Tls(tls, socket) => (tls, socket),
+        };
+        loop {
+            match tls.reader().read(buf) {
+                Err(why) if why.kind() == ErrorKind::WouldBlock => {}
+                res => return res,
+            }
+            if tls.read_tls(socket)? == 0 {
+                return Ok(0);
+            }
+            let state = tls.process_new_packets();
+            // send handshake messages and alerts, even if processing failed
+            match tls.write_tls(socket) {
+                Err(why) if why.kind() != ErrorKind::WouldBlock => return Err(why),
+                _ => {}
+            }
+            state.map_err(|why| Error::new(ErrorKind::InvalidData, why))?;
+        }
+    }
+}
+
+impl<T: Transport> Write for Stream<T> {
+    /// for TLS streams, only accepts data once all previously
+    /// encrypted data was sent, so the socket applies backpressure
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        self.flush()?;
+        let Stream::Tls(tls, socket) = self else {
+            return self.socket().write(buf);
+        };
+        let bytes = tls.writer().write(buf)?;
+        match tls.write_tls(socket) {
+            Err(why) if why.kind() != ErrorKind::WouldBlock => return Err(why),
+            _ => {}
+        }
+        match bytes {
+            // rustls buffers plaintext until the handshake is complete
+            0 if !buf.is_empty() => Err(ErrorKind::WouldBlock.into()),
+            bytes => Ok(bytes),
+        }
+    }
+
+    /// send all encrypted data, `WouldBlock` if the socket isn't ready
+    fn flush(&mut self) -> Result<(), Error> {
+        match self {
+            Stream::Plain(socket) => socket.flush(),
+            Stream::Tls(tls, socket) => {
+                while tls.wants_write() {
+                    tls.write_tls(socket)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A connection to a peer speaking the rs_chat protocol
+///
+/// The underlying [`Transport`] is nonblocking. By default, it is boxed,
+/// so connections over different transports can be handled alike. To wait for incoming packages,
+/// either [`register`](Self::register) it with a [`Poll`] or use
+/// [`wait_package`](Self::wait_package), but not both.
+///
+/// Packages that can't be sent right away are queued until the socket
+/// becomes writable again, see [`flush`](Self::flush).
+pub struct Connection<T = Box<dyn Transport>> {
+    stream: Stream<T>,
     buffer: Box<[u8; BUF_SIZE]>,
-    pkg_part: String,
+    pkg_part: Vec<u8>,
+    limits: Limits,
+    /// bytes dropped so far of an incoming package exceeding the limit
+    oversized: Option<usize>,
+    /// encoded packages waiting to be sent
+    outbox: VecDeque<Vec<u8>>,
+    /// bytes of the first package in `outbox` that were already sent
+    sent: usize,
+    /// total bytes in `outbox`, minus `sent`
+    queued: usize,
     alive: bool,
+    /// why the connection died, if it was due to an error
+    error: Option<Error>,
+    protocol: Protocol,
+    /// used by `wait_package`, as long as the connection isn't registered elsewhere
+    poll: Option<Poll>,
+}
+
+/// wait for events, retrying if interrupted by a signal
+pub fn poll_events(
+    poll: &mut Poll,
+    events: &mut Events,
+    timeout: Option<Duration>,
+) -> Result<(), Error> {
+    loop {
+        match poll.poll(events, timeout) {
+            Err(why) if why.kind() == ErrorKind::Interrupted => continue,
+            res => return res,
+        }
+    }
+}
+
+/// Protocol version and capabilities agreed on with the peer
+///
+/// Until a [`Request::Hello`](crate::requests::Request::Hello)
+/// handshake took place, the peer is assumed to speak
+/// [`LEGACY_VERSION`](Self::LEGACY_VERSION) without any capabilities.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Protocol {
+    pub version: u16,
+    pub capabilities: Vec<String>,
+}
+
+impl Protocol {
+    /// the version spoken before the handshake was introduced
+    pub const LEGACY_VERSION: u16 = 1;
+    /// the newest version, as described in `protokoll.txt`
+    pub const VERSION: u16 = 2;
+    /// the oldest version still supported
+    pub const MIN_VERSION: u16 = Self::LEGACY_VERSION;
+
+    /// agree on a protocol with a peer
+    ///
+    /// `args` contains the newest version the peer speaks,
+    /// followed by the capabilities it offers. Only offered
+    /// capabilities that are also `supported` are kept.
+    /// Returns the reason if the peer is incompatible.
+    pub fn negotiate(args: &[String], supported: &[&str]) -> Result<Self, String> {
+        let (version, offered) = args.split_first().ok_or("missing protocol version")?;
+        let version: u16 = version
+            .parse()
+            .map_err(|_| format!("invalid protocol version {version}"))?;
+        if version < Self::MIN_VERSION {
+            return Err(format!(
+                "unsupported protocol version {version}, expected {} to {}",
+                Self::MIN_VERSION,
+                Self::VERSION
+            ));
+        }
+        Ok(Self {
+            version: version.min(Self::VERSION),
+            capabilities: offered
+                .iter()
+                .filter(|c| supported.contains(&c.as_str()))
+                .cloned()
+                .collect(),
+        })
+    }
+
+    /// the arguments of a `Hello` request or its `Info` response
+    pub fn args(&self) -> Vec<String> {
+        [self.version.to_string()]
+            .into_iter()
+            .chain(self.capabilities.iter().cloned())
+            .collect()
+    }
+}
+
+impl Default for Protocol {
+    fn default() -> Self {
+        Self {
+            version: Self::LEGACY_VERSION,
+            capabilities: Vec::new(),
+        }
+    }
 }
 
 impl Connection {
-    pub fn new(stream: TcpStream) -> Result<Self, Error> {
+    /// connect to a server over TCP
+    pub fn to(addr: impl ToSocketAddrs) -> Result<Self, Error> {
+        Ok(Self::new(Box::new(Self::connect(addr)?)))
+    }
+
+    /// connect to a TLS server, verifying its certificate for `name`
+    ///
+    /// The handshake starts right away, but isn't awaited.
+    /// Errors are reported through [`take_error`](Self::take_error)
+    /// once the connection died.
+    pub fn to_tls(
+        addr: impl ToSocketAddrs,
+        name: ServerName<'static>,
+        config: Arc<ClientConfig>,
+    ) -> Result<Self, Error> {
+        let stream = Box::new(Self::connect(addr)?);
+        let tls = ClientConnection::new(config, name).map_err(Error::other)?;
+        let mut conn = Self::with_stream(Stream::Tls(Box::new(tls.into()), stream));
+        conn.flush();
+        Ok(conn)
+    }
+
+    fn connect(addr: impl ToSocketAddrs) -> Result<TcpStream, Error> {
+        // connect blocking, so errors are reported right away
+        let stream = std::net::TcpStream::connect(addr)?;
         stream.set_nonblocking(true)?;
-        Ok(Self {
+        Ok(TcpStream::from_std(stream))
+    }
+}
+
+impl<T: Transport> Connection<T> {
+    const INTEREST: Interest = Interest::READABLE.add(Interest::WRITABLE);
+
+    pub fn new(stream: T) -> Self {
+        Self::with_stream(Stream::Plain(stream))
+    }
+
+    /// accept a TLS connection, the handshake is done while reading
+    pub fn new_tls(stream: T, config: Arc<ServerConfig>) -> Result<Self, Error> {
+        let tls = ServerConnection::new(config).map_err(Error::other)?;
+        Ok(Self::with_stream(Stream::Tls(Box::new(tls.into()), stream)))
+    }
+
+    fn with_stream(stream: Stream<T>) -> Self {
+        Self {
             stream,
             buffer: Box::new([0; BUF_SIZE]),
-            pkg_part: String::new(),
+            pkg_part: Vec::new(),
+            limits: Limits::UNLIMITED,
+            oversized: None,
+            outbox: VecDeque::new(),
+            sent: 0,
+            queued: 0,
             alive: true,
-        })
+            error: None,
+            protocol: Protocol::default(),
+            poll: None,
+        }
     }
 
-    pub fn to(addr: impl ToSocketAddrs) -> Result<Self, Error> {
-        Self::new(TcpStream::connect(addr)?)
+    /// get notified through `registry` whenever data arrives
+    /// or queued packages can be sent
+    pub fn register(&mut self, registry: &Registry, token: Token) -> Result<(), Error> {
+        if let Some(poll) = self.poll.take() {
+            poll.registry().deregister(self.stream.socket())?;
+        }
+        registry.register(self.stream.socket(), token, Self::INTEREST)
+    }
+
+    pub fn deregister(&mut self, registry: &Registry) -> Result<(), Error> {
+        registry.deregister(self.stream.socket())
     }
 
     pub fn alive(&self) -> bool {
         self.alive
     }
 
+    /// the error that closed the connection, e.g. a failed TLS handshake
+    pub fn take_error(&mut self) -> Option<Error> {
+        self.error.take()
+    }
+
+    fn fail(&mut self, why: Error) {
+        self.alive = false;
+        self.error.get_or_insert(why);
+    }
+
+    /// reject incoming packages exceeding `limits`
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// the protocol negotiated with the peer
+    pub fn protocol(&self) -> &Protocol {
+        &self.protocol
+    }
+
+    pub fn set_protocol(&mut self, protocol: Protocol) {
+        self.protocol = protocol;
+    }
+
+    /// number of bytes waiting to be sent
+    pub fn queued(&self) -> usize {
+        self.queued
+    }
+
+    /// send a package, or queue it if the socket isn't writable
     pub fn send_package(&mut self, pkg: impl Borrow<Package>) {
         if cfg!(debug_assertions) {
-            println!("> {:?}", pkg.borrow());
+            println!("> {:?}", pkg.borrow().redacted());
         }
         if !self.alive {
             return;
         }
         let full_pkg: String = pkg.borrow().parts().collect();
-        if self.stream.write_all(full_pkg.as_bytes()).is_err() {
-            self.alive = false;
+        self.queued += full_pkg.len();
+        self.outbox.push_back(full_pkg.into_bytes());
+        self.flush();
+    }
+
+    /// send as much of the queued data as the socket accepts
+    pub fn flush(&mut self) {
+        match self.stream.flush() {
+            Ok(()) => {}
+            Err(why) if why.kind() == ErrorKind::WouldBlock => return,
+            Err(why) if why.kind() == ErrorKind::Interrupted => {}
+            Err(why) => return self.fail(why),
+        }
+        while let Some(front) = self.outbox.front() {
+            if !self.alive {
+                return;
+            }
+            match self.stream.write(&front[self.sent..]) {
+                Ok(0) => self.alive = false,
+                Ok(bytes) => {
+                    self.sent += bytes;
+                    self.queued -= bytes;
+                    if self.sent == front.len() {
+                        self.outbox.pop_front();
+                        self.sent = 0;
+                    }
+                }
+                Err(why) if why.kind() == ErrorKind::WouldBlock => return,
+                Err(why) if why.kind() == ErrorKind::Interrupted => {}
+                Err(why) => self.fail(why),
+            }
         }
     }
 
+    /// give up on everything not sent yet and close the connection
+    ///
+    /// `last` replaces the queued packages. It is only sent
+    /// if the socket accepts it right away.
+    pub fn close_with(&mut self, last: impl Borrow<Package>) {
+        // a partially sent package has to be completed first
+        let partial = (self.sent > 0).then(|| self.outbox.pop_front()).flatten();
+        self.outbox.clear();
+        self.queued = partial.as_ref().map_or(0, |p| p.len() - self.sent);
+        self.outbox.extend(partial);
+        self.send_package(last);
+        self.alive = false;
+    }
+
+    /// get the next package, skipping invalid ones
     pub fn get_package(&mut self) -> Option<Package> {
-        if !self.alive {
-            return None;
+        loop {
+            if let Ok(pkg) = self.read_package()? {
+                return Some(pkg);
+            }
         }
-        let mut read_bytes = BUF_SIZE;
-        while read_bytes == BUF_SIZE {
-            read_bytes = match self.stream.read(&mut *self.buffer) {
-                Ok(bytes) => bytes,
-                Err(why) => {
-                    if why.kind() != ErrorKind::WouldBlock {
-                        self.alive = false;
-                        return None;
+    }
+
+    /// get the next package, reporting packages that couldn't be decoded
+    ///
+    /// Bytes are buffered until a complete package has been received,
+    /// so characters split across multiple reads are decoded correctly.
+    ///
+    /// Packages exceeding the [`Limits`] are reported once and dropped
+    /// without being buffered. If a package exceeds them grossly,
+    /// the connection is closed.
+    pub fn read_package(&mut self) -> Option<Result<Package, PackageParseError>> {
+        loop {
+            // packages received before the connection closed are still delivered
+            let drained = !self.alive || self.fill();
+            match self.next_buffered() {
+                Some(ret) => {
+                    if cfg!(debug_assertions) {
+                        match &ret {
+                            Ok(pkg) => println!("< {:?}", pkg.redacted()),
+                            Err(why) => println!("< {why}"),
+                        }
                     }
-                    break;
+                    return Some(ret);
                 }
-            };
-            if read_bytes == 0 {
+                None if drained => return None,
+                // only dropped parts of an oversized package, there is more to read
+                None => {}
+            }
+        }
+    }
+
+    /// read until the socket is drained or the buffer exceeds the size limit
+    ///
+    /// Returns whether the socket was drained.
+    fn fill(&mut self) -> bool {
+        while self.pkg_part.len() <= self.limits.max_len {
+            match self.stream.read(&mut *self.buffer) {
+                Ok(0) => {
+                    self.alive = false;
+                    return true;
+                }
+                // a short read doesn't mean the socket is drained,
+                // TLS may return one record at a time
+                Ok(bytes) => self.pkg_part.extend_from_slice(&self.buffer[..bytes]),
+                Err(why) if why.kind() == ErrorKind::WouldBlock => return true,
+                Err(why) if why.kind() == ErrorKind::Interrupted => {}
+                Err(why) => {
+                    self.fail(why);
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /// take the next package out of the buffer
+    fn next_buffered(&mut self) -> Option<Result<Package, PackageParseError>> {
+        let end = Package::PKG_END.as_bytes()[0];
+        let idx = self.pkg_part.iter().position(|b| *b == end);
+        if let Some(dropped) = self.oversized {
+            let rest = idx.map_or(self.pkg_part.len(), |idx| idx + 1);
+            self.pkg_part.drain(..rest);
+            let dropped = dropped.saturating_add(rest);
+            if dropped / GROSS_VIOLATION > self.limits.max_len {
                 self.alive = false;
                 return None;
             }
-            self.pkg_part += &String::from_utf8_lossy(&self.buffer[..read_bytes]);
+            if idx.is_none() {
+                self.oversized = Some(dropped);
+                return None;
+            }
+            self.oversized = None;
+            return self.next_buffered();
         }
-        if let Some(idx) = self.pkg_part.find(Package::PKG_END) {
-            let (curr, next) = self.pkg_part.split_at(idx + 1);
-            let ret = Package::parse(curr);
-            self.pkg_part = String::from(next);
-            if cfg!(debug_assertions) {
-                ret.as_ref().inspect(|pkg| println!("< {pkg:?}"));
+        let too_long = Err(PackageParseError::TooLong(self.limits.max_len));
+        let Some(idx) = idx else {
+            if self.pkg_part.len() > self.limits.max_len {
+                self.oversized = Some(self.pkg_part.len());
+                self.pkg_part.clear();
+                return Some(too_long);
             }
-            return ret;
+            return None;
+        };
+        let curr: Vec<_> = self.pkg_part.drain(..=idx).collect();
+        if curr.len() > self.limits.max_len {
+            return Some(too_long);
         }
-        None
+        let pkg = String::from_utf8(curr)
+            .map_err(|_| PackageParseError::InvalidUtf8)
+            .and_then(|curr| Package::parse(&curr).ok_or(PackageParseError::Malformed));
+        Some(pkg.and_then(|pkg| {
+            if pkg.args.len() > self.limits.max_args {
+                Err(PackageParseError::TooManyArgs(self.limits.max_args))
+            } else {
+                Ok(pkg)
+            }
+        }))
     }
 
+    /// block until the next valid package arrives
+    ///
+    /// Returns `None` once the connection is closed.
+    /// Must not be used while the connection is [`register`](Self::register)ed.
     pub fn wait_package(&mut self) -> Option<Package> {
-        while self.alive {
+        loop {
             if let Some(pkg) = self.get_package() {
                 return Some(pkg);
             }
-            thread::sleep(Duration::from_millis(5));
+            if !self.alive {
+                return None;
+            }
+            self.flush();
+            if self.alive {
+                if let Err(why) = self.wait_ready() {
+                    self.fail(why);
+                }
+            }
+        }
+    }
+
+    fn wait_ready(&mut self) -> Result<(), Error> {
+        if self.poll.is_none() {
+            let poll = Poll::new()?;
+            poll.registry()
+                .register(self.stream.socket(), Token(0), Self::INTEREST)?;
+            self.poll = Some(poll);
+        }
+        let poll = self.poll.as_mut().expect("poll was just created");
+        poll_events(poll, &mut Events::with_capacity(1), None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+
+    use super::*;
+    use crate::transport::MemoryStream;
+
+    /// a connection and the raw other end, everything written is readable right away
+    fn pair() -> (Connection<MemoryStream>, MemoryStream) {
+        let (local, peer) = MemoryStream::pair();
+        (Connection::new(local), peer)
+    }
+
+    #[test]
+    fn split_characters() {
+        let (mut conn, mut peer) = pair();
+        let pkg = Package {
+            cmd: "msg".to_string(),
+            args: vec!["grüße 🦀".to_string()],
+            tag: None,
+        };
+        let bytes: String = pkg.parts().collect();
+        let bytes = bytes.as_bytes();
+        // split in the middle of the 'ü'
+        let split = bytes.iter().position(|b| *b == 0xc3).unwrap() + 1;
+        peer.write_all(&bytes[..split]).unwrap();
+        assert!(conn.read_package().is_none());
+        peer.write_all(&bytes[split..]).unwrap();
+        let received = conn.wait_package().unwrap();
+        assert_eq!(received.args, pkg.args);
+
+        peer.write_all(b"\x02msg\x16\xff\x19\x03").unwrap();
+        assert!(matches!(
+            conn.read_package(),
+            Some(Err(PackageParseError::InvalidUtf8))
+        ));
+        assert!(conn.alive());
+    }
+
+    #[test]
+    fn closed_by_peer() {
+        let (mut conn, mut peer) = pair();
+        peer.write_all(b"\x02err\x16bye\x19\x03\x02ping\x16\x03")
+            .unwrap();
+        drop(peer);
+        assert_eq!(conn.wait_package().unwrap().cmd, "err");
+        assert!(!conn.alive());
+        assert_eq!(conn.wait_package().unwrap().cmd, "ping");
+        assert!(conn.wait_package().is_none());
+    }
+
+    #[test]
+    fn limits() {
+        let (mut conn, mut peer) = pair();
+        conn.set_limits(Limits {
+            max_len: 32,
+            max_args: 2,
+        });
+        peer.write_all(b"\x02ping\x16\x03").unwrap();
+        peer.write_all(format!("\x02post\x16{}\x19\x03", "x".repeat(100)).as_bytes())
+            .unwrap();
+        peer.write_all(b"\x02cmd\x16a\x19b\x19c\x19\x03\x02ping\x16\x03")
+            .unwrap();
+        assert!(matches!(conn.read_package(), Some(Ok(p)) if p.cmd == "ping"));
+        assert!(matches!(
+            conn.read_package(),
+            Some(Err(PackageParseError::TooLong(32)))
+        ));
+        assert!(matches!(
+            conn.read_package(),
+            Some(Err(PackageParseError::TooManyArgs(2)))
+        ));
+        assert!(matches!(conn.read_package(), Some(Ok(p)) if p.cmd == "ping"));
+        assert!(conn.read_package().is_none());
+
+        // an endless package is dropped without being buffered, until it gets too long
+        peer.write_all(&[b'x'; 100]).unwrap();
+        assert!(matches!(
+            conn.read_package(),
+            Some(Err(PackageParseError::TooLong(32)))
+        ));
+        peer.write_all(&[b'x'; 300]).unwrap();
+        assert!(conn.read_package().is_none());
+        assert!(conn.alive());
+        peer.write_all(&[b'x'; 300]).unwrap();
+        assert!(conn.read_package().is_none());
+        assert!(!conn.alive());
+    }
+
+    #[test]
+    fn backpressure() {
+        let (mut conn, mut peer) = pair();
+        let pkg = Package {
+            cmd: "msg".to_string(),
+            args: vec!["x".repeat(1000)],
+            tag: None,
+        };
+        let mut sent = 0;
+        while conn.queued() == 0 {
+            conn.send_package(&pkg);
+            sent += 1;
+        }
+        assert!(conn.alive());
+        let mut received = 0;
+        let mut buf = [0; 4096];
+        while received < sent {
+            conn.flush();
+            // read until empty, so the next flush makes progress
+            while let Ok(bytes) = peer.read(&mut buf) {
+                received += buf[..bytes].iter().filter(|b| **b == 3).count();
+            }
         }
-        None
+        assert_eq!(conn.queued(), 0);
+    }
+
+    #[test]
+    fn negotiation() {
+        let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let protocol = Protocol::negotiate(&args(&["99", "tags", "unknown"]), &["basic", "tags"]);
+        assert_eq!(
+            protocol,
+            Ok(Protocol {
+                version: Protocol::VERSION,
+                capabilities: args(&["tags"]),
+            })
+        );
+        assert_eq!(
+            Protocol::negotiate(&protocol.unwrap().args(), &["tags"])
+                .unwrap()
+                .version,
+            Protocol::VERSION
+        );
+        assert!(Protocol::negotiate(&args(&["0"]), &[]).is_err());
+        assert!(Protocol::negotiate(&args(&["two"]), &[]).is_err());
+        assert!(Protocol::negotiate(&[], &[]).is_err());
     }
 }
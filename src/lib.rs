@@ -4,19 +4,37 @@ mod package;
 mod requests;
 mod response;
 mod server;
+mod tls;
+mod transport;
 
-pub use client::{ListenClient, PrimaryClient, SecondaryClient, TrivialClient};
-pub use server::Server;
+pub use client::{Credentials, ListenClient, PrimaryClient, SecondaryClient, TrivialClient};
+pub use connection::Connection;
+pub use package::Package;
+pub use requests::Request;
+pub use response::Response;
+pub use server::{
+    ConfigErr, Metrics, RateLimit, Server, ServerBuilder, ServerConfig, ServerHandle, TlsConfig,
+};
+pub use tls::Trust;
+pub use transport::{MemoryStream, Transport};
 
 pub const SERVER_PORT: u16 = 6447;
 
 /// move `N` strings out of a `Vec`
-pub fn move_vec<const N: usize>(vec: Vec<String>) -> Option<[String; N]> {
+///
+/// `names` describes the expected strings in case some are missing.
+/// Any further strings are ignored, so newer peers may add arguments.
+pub fn move_vec<const N: usize>(
+    vec: Vec<String>,
+    names: &'static str,
+) -> Result<[String; N], package::PackageParseError> {
     const EMPTY_STRING: String = String::new();
     let mut ret = [EMPTY_STRING; N];
     let mut iter = vec.into_iter();
     for item in ret.iter_mut().take(N) {
-        *item = iter.next()?;
+        *item = iter
+            .next()
+            .ok_or(package::PackageParseError::MissingArgs(names))?;
     }
-    Some(ret)
+    Ok(ret)
 }
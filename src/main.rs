@@ -1,5 +1,13 @@
-use clap::{Parser, Subcommand};
-use rs_chat::{ListenClient, PrimaryClient, SecondaryClient, Server, TrivialClient};
+use std::{net::IpAddr, path::PathBuf};
+
+use clap::{Args, Parser, Subcommand};
+use rs_chat::{
+    ConfigErr, Credentials, ListenClient, PrimaryClient, SecondaryClient, Server, ServerConfig,
+    TlsConfig, TrivialClient, Trust,
+};
+
+/// environment variable to take passwords from, see [`LoginArgs`]
+const PASSWORD_VAR: &str = "RS_CHAT_PASSWORD";
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -11,14 +19,18 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     /// Start a server
-    Server,
+    Server(ServerArgs),
     /// Start the primary client
     Read {
         /// connection string
         ///
         /// must be in the format `name@address`,
-        /// e.g. `me@localhost`
+        /// e.g. `me@localhost` or `me@localhost:7000`
         conn: String,
+        #[command(flatten)]
+        login: LoginArgs,
+        #[command(flatten)]
+        tls: TlsArgs,
     },
     /// Start the secondary client
     Write {
@@ -29,27 +41,179 @@ enum Commands {
     Test {
         /// connection string
         conn: String,
+        #[command(flatten)]
+        login: LoginArgs,
+        #[command(flatten)]
+        tls: TlsArgs,
     },
     /// Start the passive client
     Listen {
         /// address of server to connect to
+        ///
+        /// may include a port, e.g. `localhost:7000`
         addr: String,
+        #[command(flatten)]
+        tls: TlsArgs,
     },
 }
 
+/// How a client logs in
+///
+/// Passwords are never taken as arguments, as those show up in the process list.
+/// They are read from the environment variable `RS_CHAT_PASSWORD`
+/// if it is set, or asked for on stdin otherwise.
+#[derive(Args)]
+struct LoginArgs {
+    /// log into a registered account, asking for its password
+    ///
+    /// if omitted, you are logged in as a guest
+    #[arg(short, long)]
+    password: bool,
+    /// register a new account, asking for its password
+    #[arg(short, long, conflicts_with = "password")]
+    register: bool,
+}
+
+impl LoginArgs {
+    fn credentials(self) -> Credentials {
+        if self.register {
+            Credentials::Register(read_password())
+        } else if self.password {
+            Credentials::Password(read_password())
+        } else {
+            Credentials::Guest
+        }
+    }
+}
+
+fn read_password() -> String {
+    if let Ok(password) = std::env::var(PASSWORD_VAR) {
+        return password;
+    }
+    eprint!("password: ");
+    let mut password = String::new();
+    if let Err(why) = std::io::stdin().read_line(&mut password) {
+        eprintln!("failed to read password: {why}");
+        std::process::exit(1);
+    }
+    password.trim_end_matches(['\r', '\n']).to_string()
+}
+
+/// How a client connects to a TLS server
+///
+/// Without any of these, the connection is unencrypted.
+#[derive(Args)]
+struct TlsArgs {
+    /// connect using TLS, trusting the CA certificates in this PEM file
+    #[arg(long, value_name = "PEM")]
+    ca: Option<PathBuf>,
+    /// connect using TLS, trusting only the certificate in this PEM file
+    ///
+    /// useful for self-signed certificates
+    #[arg(long, value_name = "PEM", conflicts_with = "ca")]
+    pin: Option<PathBuf>,
+}
+
+impl TlsArgs {
+    fn trust(self) -> Option<Trust> {
+        self.ca.map(Trust::Ca).or(self.pin.map(Trust::Pinned))
+    }
+}
+
+/// Server settings
+///
+/// Settings given on the command line take
+/// precedence over those in the config file.
+#[derive(Args)]
+struct ServerArgs {
+    /// path to a TOML config file
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    /// address of an interface to listen on, may be repeated
+    #[arg(short, long)]
+    bind: Vec<IpAddr>,
+    /// port to listen on
+    #[arg(short, long)]
+    port: Option<u16>,
+    /// also listen on IPv6
+    #[arg(long)]
+    ipv6: bool,
+    /// only allow registered users to log in
+    #[arg(long)]
+    no_guests: bool,
+    /// account allowed to kick, ban and mute users, may be repeated
+    #[arg(short, long, value_name = "NAME")]
+    admin: Vec<String>,
+    /// only accept TLS connections, using the certificate chain in this PEM file
+    #[arg(long, value_name = "PEM", requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+    /// private key of the TLS certificate, as PEM file
+    #[arg(long, value_name = "PEM", requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+}
+
+impl ServerArgs {
+    /// read the config file, if any, and apply the command line options
+    fn config(&self) -> Result<ServerConfig, ConfigErr> {
+        let mut config = match &self.config {
+            Some(path) => ServerConfig::load(path)?,
+            None => ServerConfig::default(),
+        };
+        if !self.bind.is_empty() {
+            config.bind = self.bind.clone();
+        }
+        if let Some(port) = self.port {
+            config.port = port;
+        }
+        config.ipv6 |= self.ipv6;
+        config.guests &= !self.no_guests;
+        config.admins.extend(self.admin.iter().cloned());
+        if let (Some(cert), Some(key)) = (&self.tls_cert, &self.tls_key) {
+            config.tls = Some(TlsConfig {
+                cert: cert.clone(),
+                key: key.clone(),
+            });
+        }
+        Ok(config)
+    }
+}
+
 fn main() {
     match Cli::parse().command {
-        Commands::Server => Server::new().unwrap().run(),
-        Commands::Read { conn } => {
+        Commands::Server(args) => {
+            let config = args.config().unwrap_or_else(|why| {
+                eprintln!("{why}");
+                std::process::exit(1);
+            });
+            let mut server = Server::new(config).unwrap();
+            server.attach_console(move || args.config());
+            let handle = server.handle();
+            ctrlc::set_handler(move || {
+                if !handle.is_running() {
+                    // asked twice, don't wait for slow clients
+                    std::process::exit(1);
+                }
+                handle.stop();
+            })
+            .expect("failed to handle signals");
+            server.run();
+        }
+        Commands::Read { conn, login, tls } => {
             let (name, addr) = conn_str(&conn);
-            PrimaryClient::connect(addr, name).unwrap().run();
+            PrimaryClient::connect(addr, name, login.credentials(), tls.trust().as_ref())
+                .unwrap()
+                .run();
         }
         Commands::Write { port } => SecondaryClient::connect(port).unwrap().run(),
-        Commands::Test { conn } => {
+        Commands::Test { conn, login, tls } => {
             let (name, addr) = conn_str(&conn);
-            TrivialClient::connect(addr, name).unwrap().run();
+            TrivialClient::connect(addr, name, login.credentials(), tls.trust().as_ref())
+                .unwrap()
+                .run();
         }
-        Commands::Listen { addr } => ListenClient::connect(&addr).unwrap().run(),
+        Commands::Listen { addr, tls } => ListenClient::connect(&addr, tls.trust().as_ref())
+            .unwrap()
+            .run(),
     }
 }
 
@@ -70,4 +234,10 @@ mod test {
     fn cli() {
         Cli::command().debug_assert();
     }
+
+    #[test]
+    fn no_password_args() {
+        assert!(Cli::try_parse_from(["rs_chat", "read", "me@localhost", "-p"]).is_ok());
+        assert!(Cli::try_parse_from(["rs_chat", "read", "me@localhost", "-p", "secret"]).is_err());
+    }
 }
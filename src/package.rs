@@ -1,11 +1,17 @@
-use std::fmt::Display;
+use std::{borrow::Cow, fmt::Display};
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Package {
     pub cmd: String,
     pub args: Vec<String>,
+    /// optional id chosen by the sender of a request
+    ///
+    /// If the `tags` capability was negotiated, it
+    /// is echoed on the corresponding synchronous response.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
 }
 
 impl Package {
@@ -13,41 +19,134 @@ impl Package {
     pub const CMD_END: &'static str = "\x16"; // SYN
     pub const ARG_END: &'static str = "\x19"; // EM
     pub const PKG_END: &'static str = "\x03"; // ETX
+    pub const TAG_END: &'static str = "\x1f"; // US
+    pub const ESCAPE: char = '\x10'; // DLE
+
+    /// characters that have to be escaped inside commands and arguments
+    ///
+    /// Each of these is replaced by [`ESCAPE`](Self::ESCAPE), followed by the
+    /// character shifted into the printable range (e.g. ETX becomes `DLE C`).
+    const SPECIAL: [char; 6] = ['\x02', '\x16', '\x19', '\x03', '\x1f', Self::ESCAPE];
+    const SHIFT: u8 = 0x40;
+    /// requests taking a password as their second argument
+    const SECRET_CMDS: [&'static str; 5] = [
+        "auth",
+        "register",
+        "new_channel",
+        "subscribe",
+        "channel_password",
+    ];
 
     pub fn parse(src: &str) -> Option<Self> {
         let inner = src
             .strip_prefix(Self::PKG_START)?
             .strip_suffix(Self::PKG_END)?;
-        let (cmd, args) = inner.split_once(Self::CMD_END)?;
-        let mut args: Vec<_> = args.split(Self::ARG_END).map(String::from).collect();
+        let (head, args) = inner.split_once(Self::CMD_END)?;
+        let (tag, cmd) = match head.split_once(Self::TAG_END) {
+            Some((tag, cmd)) => (Some(Self::unescape(tag)?), cmd),
+            None => (None, head),
+        };
+        let mut args = args
+            .split(Self::ARG_END)
+            .map(Self::unescape)
+            .collect::<Option<Vec<_>>>()?;
         args.pop();
         Some(Self {
-            cmd: cmd.to_string(),
+            cmd: Self::unescape(cmd)?,
             args,
+            tag,
         })
     }
 
-    pub fn parts(&self) -> impl Iterator<Item = &str> {
-        [Self::PKG_START, &self.cmd, Self::CMD_END]
+    /// replace the tag of this package
+    pub fn with_tag(mut self, tag: Option<String>) -> Self {
+        self.tag = tag;
+        self
+    }
+
+    /// this package with any password hidden, e.g. to print it
+    pub fn redacted(&self) -> Cow<'_, Self> {
+        if !Self::SECRET_CMDS.contains(&self.cmd.as_str()) || self.args.len() < 2 {
+            return Cow::Borrowed(self);
+        }
+        let mut pkg = self.clone();
+        pkg.args[1] = "<redacted>".to_string();
+        Cow::Owned(pkg)
+    }
+
+    pub fn parts(&self) -> impl Iterator<Item = Cow<'_, str>> {
+        let tag = self
+            .tag
+            .iter()
+            .flat_map(|t| [Self::escape(t), Cow::Borrowed(Self::TAG_END)]);
+        [Cow::Borrowed(Self::PKG_START)]
             .into_iter()
-            .chain(self.args.iter().flat_map(|a| [a, Self::ARG_END]))
-            .chain([Self::PKG_END])
+            .chain(tag)
+            .chain([Self::escape(&self.cmd), Cow::Borrowed(Self::CMD_END)])
+            .chain(
+                self.args
+                    .iter()
+                    .flat_map(|a| [Self::escape(a), Cow::Borrowed(Self::ARG_END)]),
+            )
+            .chain([Cow::Borrowed(Self::PKG_END)])
+    }
+
+    fn escape(src: &str) -> Cow<'_, str> {
+        if !src.contains(Self::SPECIAL) {
+            return Cow::Borrowed(src);
+        }
+        let mut escaped = String::with_capacity(src.len() + 2);
+        for c in src.chars() {
+            if Self::SPECIAL.contains(&c) {
+                escaped.push(Self::ESCAPE);
+                escaped.push((c as u8 + Self::SHIFT) as char);
+            } else {
+                escaped.push(c);
+            }
+        }
+        Cow::Owned(escaped)
+    }
+
+    fn unescape(src: &str) -> Option<String> {
+        let mut unescaped = String::with_capacity(src.len());
+        let mut chars = src.chars();
+        while let Some(c) = chars.next() {
+            if c == Self::ESCAPE {
+                let shifted = chars.next().filter(char::is_ascii)? as u8;
+                let orig = shifted.checked_sub(Self::SHIFT)? as char;
+                Self::SPECIAL.contains(&orig).then_some(())?;
+                unescaped.push(orig);
+            } else {
+                unescaped.push(c);
+            }
+        }
+        Some(unescaped)
     }
 }
 
 #[derive(Debug)]
 pub enum PackageParseError {
+    InvalidUtf8,
+    Malformed,
     UnknownCmd(String),
     MissingArgs(&'static str),
+    /// the package is longer than the given number of bytes
+    TooLong(usize),
+    /// the package has more than the given number of arguments
+    TooManyArgs(usize),
 }
 
 impl Display for PackageParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            PackageParseError::InvalidUtf8 => write!(f, "package is not valid UTF-8"),
+            PackageParseError::Malformed => write!(f, "malformed package"),
             PackageParseError::UnknownCmd(cmd) => write!(f, "unknown command {cmd}"),
             PackageParseError::MissingArgs(args) => {
                 write!(f, "insufficient args provided, expected [{args}]")
             }
+            PackageParseError::TooLong(max) => write!(f, "package exceeds {max} bytes"),
+            PackageParseError::TooManyArgs(max) => write!(f, "more than {max} args provided"),
         }
     }
 }
@@ -89,6 +188,7 @@ macro_rules! package_enum {
         }
 
         impl ::std::convert::From<$enum> for $crate::package::Package {
+            #[allow(clippy::needless_update)]
             fn from(value: $enum) -> Self {
                 match value {
                     $($enum::$var $((
@@ -111,8 +211,7 @@ macro_rules! package_enum {
                     $(
                         $cmd => {
                             $(
-                                $(let [$($arg),+] = $crate::move_vec(value.args)
-                                    .ok_or($crate::package::PackageParseError::MissingArgs(stringify!($($arg),+)))?;)?
+                                $(let [$($arg),+] = $crate::move_vec(value.args, stringify!($($arg),+))?;)?
                                 $(let $vec = value.args;)?
                             )?
                             Self::$var $(($($vec)? $($($arg),+)?))?
@@ -142,6 +241,73 @@ macro_rules! check_exactly_one {
 
 #[cfg(test)]
 mod test {
+    use super::Package;
+
+    fn round_trip(cmd: &str, args: &[&str]) {
+        let pkg = Package {
+            cmd: cmd.to_string(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+            tag: None,
+        };
+        let encoded: String = pkg.parts().collect();
+        let inner = &encoded[1..encoded.len() - 1];
+        assert!(!inner.contains(Package::PKG_START));
+        assert!(!inner.contains(Package::PKG_END));
+        let parsed = Package::parse(&encoded).expect("encoded package should parse");
+        assert_eq!(parsed.cmd, pkg.cmd);
+        assert_eq!(parsed.args, pkg.args);
+    }
+
+    #[test]
+    fn escaping() {
+        round_trip("cmd", &[]);
+        round_trip("cmd", &["", ""]);
+        round_trip("cmd", &["a b", "äöü 🦀"]);
+        for c in (0..0x20u8).chain([0x7f]).map(char::from) {
+            let s = format!("a{c}b{c}");
+            round_trip(&s, &[&s, &c.to_string(), ""]);
+        }
+        round_trip("\x10C", &["\x10\x10", "\x10\x03\x19", "\x02\x16"]);
+    }
+
+    #[test]
+    fn tags() {
+        let untagged = Package::parse("\x02ping\x16\x03").unwrap();
+        assert_eq!(untagged.tag, None);
+        let tagged = Package::parse("\x0242\x1fping\x16\x03").unwrap();
+        assert_eq!(tagged.cmd, "ping");
+        assert_eq!(tagged.tag.as_deref(), Some("42"));
+        let pkg = untagged.with_tag(Some("a\x1fb".to_string()));
+        let encoded: String = pkg.parts().collect();
+        assert_eq!(encoded.matches(Package::TAG_END).count(), 1);
+        let parsed = Package::parse(&encoded).unwrap();
+        assert_eq!(parsed.tag, pkg.tag);
+        assert_eq!(parsed.cmd, "ping");
+    }
+
+    #[test]
+    fn redacted() {
+        let auth = Package {
+            cmd: "auth".to_string(),
+            args: vec!["me".to_string(), "secret".to_string()],
+            tag: None,
+        };
+        assert!(!format!("{:?}", auth.redacted()).contains("secret"));
+        assert_eq!(auth.redacted().args[0], "me");
+        let post = Package {
+            cmd: "post".to_string(),
+            ..auth
+        };
+        assert_eq!(post.redacted().args[1], "secret");
+    }
+
+    #[test]
+    fn invalid_escapes() {
+        assert!(Package::parse("\x02cmd\x16a\x10\x03").is_none());
+        assert!(Package::parse("\x02cmd\x16a\x10A\x19\x03").is_none());
+        assert!(Package::parse("\x02cmd\x16a\x10ä\x19\x03").is_none());
+    }
+
     // just to test it compiles
     package_enum! {
         #[derive(Debug)]
@@ -151,4 +317,17 @@ mod test {
             Args("args" => arg1, arg2),
         }
     }
+
+    #[test]
+    fn extra_args() {
+        let pkg = |args: &[&str]| Package {
+            cmd: "args".to_string(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+            tag: None,
+        };
+        assert!(Test::try_from(pkg(&["a"])).is_err());
+        // newer peers may send more
+        let parsed = Test::try_from(pkg(&["a", "b", "c"])).unwrap();
+        assert!(matches!(parsed, Test::Args(a, b) if a == "a" && b == "b"));
+    }
 }
@@ -1,3 +1,5 @@
+use std::net::IpAddr;
+
 use crate::{
     package::{Package, PackageParseError},
     package_enum,
@@ -9,8 +11,30 @@ package_enum! {
     /// 
     /// Each such request will be responded to with EXACTLY one of
     /// the synchronous [`Response`]s.
+    /// 
+    /// Besides the error cases listed for each request, a server may
+    /// reject any request if the client makes it too often.
     pub enum Request {
-        /// Log into a server with the given name
+        /// Agree on a protocol version before logging in
+        /// 
+        /// The first argument is the newest protocol version the
+        /// client speaks, followed by the capabilities (features)
+        /// it would like to use. Clients skipping this request
+        /// are assumed to speak the legacy protocol version 1.
+        /// Such clients receive `Msg` without id and time, but no other
+        /// asynchronous responses, and tags are only echoed if the
+        /// `tags` capability was negotiated.
+        /// 
+        /// This request is responded to with `Info` in case of success,
+        /// containing the negotiated version, followed by all offered
+        /// capabilities the server supports.
+        /// 
+        /// ## Error cases
+        /// * the client is already logged on
+        /// * the version is missing or invalid
+        /// * the server doesn't support any version the client speaks
+        Hello("hello" => [args]),
+        /// Log into a server with the given name as a guest
         /// 
         /// This request is responded to with `Ack` in case of success.
         /// 
@@ -19,9 +43,38 @@ package_enum! {
         /// 
         /// ## Error cases
         /// * the client is already logged on
+        /// * the server doesn't allow guests (see feature `guests`)
         /// * the name is rejected by the server
+        /// * the name belongs to a registered account
         /// * the name is already used by another client
         Login("login" => name),
+        /// Log into a server with a registered account
+        /// 
+        /// This request is responded to with `Ack` in case of success.
+        /// 
+        /// For this request, the response is guaranteed to be sent
+        /// BEFORE any `Msg` response is sent to the client.
+        /// 
+        /// ## Error cases
+        /// * the client is already logged on
+        /// * the name is rejected by the server
+        /// * there is no account with that name or the password is wrong
+        /// * there were too many failed logins to the account or from the client's address
+        /// * the name is already used by another client
+        Authenticate("auth" => name, password),
+        /// Create a new account and log into it
+        /// 
+        /// This request is responded to with `Ack` in case of success.
+        /// 
+        /// For this request, the response is guaranteed to be sent
+        /// BEFORE any `Msg` response is sent to the client.
+        /// 
+        /// ## Error cases
+        /// * the client is already logged on
+        /// * the name is rejected by the server
+        /// * the name is already registered
+        /// * the name is already used by another client
+        Register("register" => name, password),
         /// Listen to the global channel of a server
         /// 
         /// This is the alternate login method available.
@@ -54,13 +107,19 @@ package_enum! {
         /// ## Error cases
         /// * the channel does not exist
         /// * you have not joined the channel
+        /// * the channel is in slow mode and you posted too recently
         Post("post" => channel, msg),
         /// Send a message to another user
         /// 
+        /// If the user is registered but currently offline, the server
+        /// stores the message and delivers it right after their next login.
+        /// 
         /// This request is responded to with `Ack` in case of success.
         /// 
         /// ## Error cases
         /// * the user does not exist
+        /// * the user is offline and can't receive any more messages
+        /// * the user is a bot
         /// * the user has blocked you
         /// * you have blocked the user
         Send("send" => name, msg),
@@ -81,6 +140,24 @@ package_enum! {
         /// * the channel does not exist
         /// * you have not joined the channel
         Names("names" => channel),
+        /// Get the most recent messages of a channel
+        /// 
+        /// To get the history of the global channel,
+        /// simply leave the channel argument empty
+        /// (as the name of the global channel is the
+        /// empty string).
+        /// 
+        /// This request is responded to with `Info` in case of success.
+        /// The response contains up to `count` messages, oldest first.
+        /// Each message is represented by four arguments:
+        /// id, time, sender and the message itself
+        /// (see [`Response::Msg`]).
+        /// 
+        /// ## Error cases
+        /// * the count is not a number
+        /// * the channel does not exist
+        /// * you have not joined the channel
+        History("history" => channel, count),
         /// Get the name of the server
         /// 
         /// This request is responded to with `Info` in case of success.
@@ -101,6 +178,10 @@ package_enum! {
         /// To create a new channel that anyone can join,
         /// simply leave the password empty.
         /// 
+        /// You become the founder of the channel. Founders with an
+        /// account keep this role when they leave and return, guests
+        /// lose it once they unsubscribe or disconnect.
+        /// 
         /// This request is responded to with `Ack` in case of success.
         /// 
         /// ## Error cases
@@ -110,10 +191,40 @@ package_enum! {
         /// List all available channels
         /// 
         /// This request is responded to with `Info` in case of success.
-        /// The response contains a list of all channels.
+        /// The response contains the name of each channel, followed by
+        /// its topic (empty if none was set). Clients speaking the legacy
+        /// protocol only get the names.
         /// 
         /// This request will never fail.
         ListChannels("list_channels"),
+        /// Get the topic of a channel
+        /// 
+        /// Anyone may read the topic of a channel.
+        /// 
+        /// This request is responded to with `Info` in case of success.
+        /// The response contains the topic as single argument,
+        /// which is empty if no topic was set.
+        /// 
+        /// ## Error cases
+        /// * the channel name is invalid
+        /// * the channel doesn't exist
+        Topic("topic" => channel),
+        /// Change the topic of a channel
+        /// 
+        /// Only operators of the channel and admins may change its topic.
+        /// As the global channel has no operators, only admins may change
+        /// its topic. All members are notified with a `Topic` response.
+        /// 
+        /// This request is responded to with `Ack` in case of success.
+        /// 
+        /// ## Error cases
+        /// * the channel name is invalid
+        /// * the topic is too long
+        /// * you are muted
+        /// * the channel doesn't exist
+        /// * you have not subscribed to the channel
+        /// * you are neither an operator of the channel nor an admin
+        SetTopic("set_topic" => channel, topic),
         /// Subscribe to a channel
         /// 
         /// This request is responded to with `Ack` in case of success.
@@ -121,6 +232,7 @@ package_enum! {
         /// ## Error cases
         /// * the channel name is invalid
         /// * the channel doesn't exist
+        /// * you are banned from the channel
         /// * the password is incorrect
         /// * you have already subscribed to the channel
         Subscribe("subscribe" => channel, password),
@@ -133,10 +245,125 @@ package_enum! {
         /// * the channel doesn't exist
         /// * you have not subscribed to the channel
         Unsubscribe("unsubscribe" => channel),
+        /// Remove a member from a channel
+        /// 
+        /// Only operators and the founder of a channel may kick
+        /// members, and only those with a lower role
+        /// (founder > operator > member).
+        /// The kicked member may subscribe again.
+        /// 
+        /// This request is responded to with `Ack` in case of success.
+        /// 
+        /// ## Error cases
+        /// * the channel or user name is invalid
+        /// * the channel doesn't exist
+        /// * you have not subscribed to the channel
+        /// * you are not an operator of the channel
+        /// * the user has not subscribed to the channel
+        /// * the user's role is not lower than yours
+        Kick("kick" => channel, name),
+        /// Ban a user from a channel
+        /// 
+        /// The user is kicked if currently subscribed and
+        /// can't subscribe to the channel until unbanned.
+        /// The same permissions as for `Kick` apply, but the
+        /// user doesn't have to be subscribed or even online.
+        /// 
+        /// This request is responded to with `Ack` in case of success.
+        /// 
+        /// ## Error cases
+        /// * the channel or user name is invalid
+        /// * the channel doesn't exist
+        /// * you have not subscribed to the channel
+        /// * you are not an operator of the channel
+        /// * the user's role is not lower than yours
+        /// * the user is already banned
+        Ban("ban" => channel, name),
+        /// Lift a ban from a channel
+        /// 
+        /// This request is responded to with `Ack` in case of success.
+        /// 
+        /// ## Error cases
+        /// * the channel or user name is invalid
+        /// * the channel doesn't exist
+        /// * you have not subscribed to the channel
+        /// * you are not an operator of the channel
+        /// * the user is not banned
+        Unban("unban" => channel, name),
+        /// Make a member an operator of a channel
+        /// 
+        /// Operators may promote other members.
+        /// 
+        /// This request is responded to with `Ack` in case of success.
+        /// 
+        /// ## Error cases
+        /// * the channel or user name is invalid
+        /// * the channel doesn't exist
+        /// * you have not subscribed to the channel
+        /// * you are not an operator of the channel
+        /// * the user has not subscribed to the channel
+        /// * the user is already an operator (or the founder)
+        Promote("promote" => channel, name),
+        /// Revoke operator privileges
+        /// 
+        /// Only the founder of a channel may demote operators.
+        /// 
+        /// This request is responded to with `Ack` in case of success.
+        /// 
+        /// ## Error cases
+        /// * the channel or user name is invalid
+        /// * the channel doesn't exist
+        /// * you have not subscribed to the channel
+        /// * you are not the founder of the channel
+        /// * the user is not an operator
+        Demote("demote" => channel, name),
+        /// Make another member the founder of a channel
+        /// 
+        /// You become an operator of the channel.
+        /// 
+        /// This request is responded to with `Ack` in case of success.
+        /// 
+        /// ## Error cases
+        /// * the channel or user name is invalid
+        /// * the channel doesn't exist
+        /// * you have not subscribed to the channel
+        /// * you are not the founder of the channel
+        /// * the user has not subscribed to the channel
+        /// * the user is already the founder
+        Handover("handover" => channel, name),
+        /// Change the password of a channel
+        /// 
+        /// Leave the password empty to allow anyone to join.
+        /// Members that have already subscribed stay subscribed.
+        /// 
+        /// This request is responded to with `Ack` in case of success.
+        /// 
+        /// ## Error cases
+        /// * the channel name is invalid
+        /// * the channel doesn't exist
+        /// * you have not subscribed to the channel
+        /// * you are not an operator of the channel
+        ChannelPassword("channel_password" => channel, password),
+        /// Make members wait between their posts to a channel
+        /// 
+        /// After posting, members have to wait the given number
+        /// of seconds before posting again. Operators are exempt.
+        /// Setting 0 seconds turns slow mode off.
+        /// 
+        /// This request is responded to with `Ack` in case of success.
+        /// 
+        /// ## Error cases
+        /// * the channel name is invalid
+        /// * the channel doesn't exist
+        /// * you have not subscribed to the channel
+        /// * you are not an operator of the channel
+        /// * the number of seconds is invalid
+        SlowMode("slow_mode" => channel, seconds),
         /// Block direct messages from a user
         /// 
         /// This prevents any direct communication between the client
         /// sending this request and the blocked client.
+        /// For registered users, blocks persist across logins.
         /// 
         /// This request is responded to with `Ack` in case of success.
         /// 
@@ -165,6 +392,8 @@ package_enum! {
         /// The response contains AT LEAST one argument with the number
         /// of your offenses. A server MAY send an additional
         /// argument containing the maximal number of offenses.
+        /// Reaching it gets you disconnected, and possibly banned
+        /// for a while. Offenses may expire after some time.
         /// 
         /// This request will never fail.
         Offenses("offenses"),
@@ -176,7 +405,67 @@ package_enum! {
         /// * the user name is invalid
         /// * the user doesn't exist
         /// * the user did not have any offenses
+        /// * you tried to pardon yourself
+        /// * you pardoned too many users recently
         Pardon("pardon" => name),
+        /// Review who pardoned whom recently
+        /// 
+        /// Only admins may use this request (see feature `admin`).
+        /// 
+        /// This request is responded to with `Info` in case of success,
+        /// containing the time (like in `Msg`), the pardoning user
+        /// and the pardoned user of each pardon, oldest first.
+        /// 
+        /// ## Error cases
+        /// * you are not an admin
+        PardonLog("pardon_log"),
+        /// Disconnect a user from the server
+        /// 
+        /// Only admins may use this request (see feature `admin`).
+        /// The user may log in again right away.
+        /// 
+        /// This request is responded to with `Ack` in case of success.
+        /// 
+        /// ## Error cases
+        /// * the user name is invalid
+        /// * you are not an admin
+        /// * the user is not online
+        /// * the user is an admin
+        ServerKick("server_kick" => name),
+        /// Ban a user or an address from the server
+        /// 
+        /// Only admins may use this request (see feature `admin`).
+        /// `target` is either a user name or an IP address, `duration`
+        /// the length of the ban in seconds. Matching clients are
+        /// disconnected and can't log in until the ban ends.
+        /// A duration of 0 lifts the ban.
+        /// 
+        /// This request is responded to with `Ack` in case of success.
+        /// 
+        /// ## Error cases
+        /// * the target is neither a valid name nor an IP address
+        /// * you are not an admin
+        /// * the duration is not a number
+        /// * the user is an admin
+        /// * lifting a ban: the target is not banned
+        ServerBan("server_ban" => target, duration),
+        /// Keep a user from posting messages
+        /// 
+        /// Only admins may use this request (see feature `admin`).
+        /// For `duration` seconds, the user can't post to channels,
+        /// send direct messages or set topics.
+        /// A duration of 0 lifts the mute.
+        /// 
+        /// This request is responded to with `Ack` in case of success.
+        /// 
+        /// ## Error cases
+        /// * the user name is invalid
+        /// * you are not an admin
+        /// * the duration is not a number
+        /// * the user doesn't exist
+        /// * the user is an admin
+        /// * lifting a mute: the user is not muted
+        Mute("mute" => name, duration),
     }
 }
 
@@ -195,19 +484,44 @@ impl Request {
     pub fn check_idents(&self) -> Result<(), RequestErr> {
         match self {
             Request::Login(name)
+            | Request::Authenticate(name, _)
+            | Request::Register(name, _)
             | Request::Send(name, _)
             | Request::Block(name)
             | Request::Unblock(name)
-            | Request::Pardon(name) => is_ident_ok(name)
+            | Request::Pardon(name)
+            | Request::ServerKick(name)
+            | Request::Mute(name, _) => is_ident_ok(name)
                 .then_some(())
                 .ok_or(RequestErr::InvalidName),
             Request::Post(channel, _)
             | Request::Names(channel)
+            | Request::History(channel, _)
             | Request::NewChannel(channel, _)
             | Request::Subscribe(channel, _)
-            | Request::Unsubscribe(channel) => is_ident_ok(channel)
+            | Request::Unsubscribe(channel)
+            | Request::ChannelPassword(channel, _)
+            | Request::SlowMode(channel, _)
+            | Request::Topic(channel)
+            | Request::SetTopic(channel, _) => is_ident_ok(channel)
+                .then_some(())
+                .ok_or(RequestErr::InvalidName),
+            Request::Kick(channel, name)
+            | Request::Ban(channel, name)
+            | Request::Unban(channel, name)
+            | Request::Promote(channel, name)
+            | Request::Demote(channel, name)
+            | Request::Handover(channel, name) => (is_ident_ok(channel) && is_ident_ok(name))
                 .then_some(())
                 .ok_or(RequestErr::InvalidName),
+            Request::ServerBan(target, _) => {
+                (is_ident_ok(target) || target.parse::<IpAddr>().is_ok())
+                    .then_some(())
+                    .ok_or(RequestErr::InvalidName)
+            }
+            Request::Hello(args) if args.is_empty() => {
+                Err(PackageParseError::MissingArgs("version").into())
+            }
             _ => Ok(()),
         }
     }
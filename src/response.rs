@@ -9,11 +9,17 @@ package_enum! {
     /// Each client request will result in EXACTLY one such
     /// response. To identify corresponding pairs, the responses
     /// are always sent in the order the requests are received.
-    /// * `Msg` is an asynchronous response to a request made by
-    /// another client (namely, the request to send you a message).
+    /// Additionally, if the `tags` capability was negotiated
+    /// through [`Hello`](crate::requests::Request::Hello),
+    /// the [`tag`](crate::package::Package::tag) of a request
+    /// is echoed on its response.
+    /// * `Msg`, `Topic` and `Member` are asynchronous responses to requests made by
+    /// another client (e.g. the request to send you a message).
+    /// `Shutdown` is asynchronous as well, but sent by the server on its own.
     /// A server may send any number of these responses at
     /// any time to any client, regardless of any other
     /// communication they might have at the moment.
+    #[derive(Debug)]
     pub enum Response {
         /// ACK: request successfull, no further data needed
         Ack("ack"),
@@ -23,14 +29,38 @@ package_enum! {
         Info("info" => [data]),
         /// MSG: you've got mail!
         /// 
-        /// Arguments are `channel`, `sender` and `message`.
+        /// Arguments are `channel`, `sender`, `message`, `id` and `time`.
         /// Channel may be empty (global channel) or
         /// `__direct` (direct messages).
-        Msg("msg" => channel, name, msg),
+        /// The id is assigned by the server and increases with every message.
+        /// The time is given in seconds since the unix epoch (UTC).
+        Msg("msg" => channel, name, msg, id, time),
+        /// TOPIC: the topic of a channel you joined has changed
+        /// 
+        /// Arguments are `channel`, the name of the member
+        /// who changed the topic, and the new `topic`.
+        Topic("topic" => channel, name, topic),
+        /// MEMBER: the members of a channel you joined have changed
+        /// 
+        /// Arguments are `channel`, the `name` of the affected client
+        /// and the `event`, which is one of
+        /// * `join`: subscribed to the channel (or logged in, for the global channel)
+        /// * `leave`: unsubscribed from the channel (or logged out)
+        /// * `kick`: was kicked by an operator or for too many offenses
+        /// * `ban`: was banned from the channel
+        Member("member" => channel, name, event),
+        /// SHUTDOWN: the server is about to close the connection
+        /// 
+        /// The only argument is the reason, e.g. that the server
+        /// is being restarted. No further responses follow.
+        Shutdown("shutdown" => why),
     }
 }
 
 impl Response {
+    /// commands of the asynchronous responses
+    pub const ASYNC: [&'static str; 4] = ["msg", "topic", "member", "shutdown"];
+
     /// should this response be counted towards a clients offenses?
     pub fn is_bad(&self) -> bool {
         matches!(self, Self::Err { .. })
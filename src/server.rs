@@ -1,79 +1,227 @@
 use std::{
+    borrow::{Borrow, Cow},
     collections::{HashMap, HashSet},
     io::Error,
-    sync::mpsc::{self, Receiver},
-    thread,
-    time::Duration,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver},
+        Arc, Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
 };
 
-//mod bot;
+use mio::{Events, Poll, Token, Waker};
+
+mod accounts;
+mod bot;
+mod builder;
+mod channel;
+mod config;
+mod console;
+mod handle;
+mod history;
 mod login;
+mod metrics;
+mod pardons;
+mod rate;
+mod sanctions;
 
-use crate::{connection::Connection, package::Package, requests::Request, response::Response};
+use crate::{
+    connection::{poll_events, Connection, Protocol},
+    package::Package,
+    requests::{Request, RequestErr},
+    response::Response,
+    transport::Transport,
+};
+use accounts::Accounts;
+use bot::{Bot, Event};
+pub use builder::ServerBuilder;
+use channel::{Channel, Role};
+pub use config::{ConfigErr, ServerConfig, TlsConfig};
+use console::Command;
+pub use handle::ServerHandle;
+use history::{History, Message};
+use login::{HashJob, Hasher, LoginMethod, LoginThread};
+pub use metrics::Metrics;
+use pardons::Pardons;
+use rate::Bucket;
+pub use rate::RateLimit;
+use sanctions::Sanctions;
 
 pub const GLOBAL_CHANNEL_NAME: &str = "";
 pub const DIRECT_CHANNEL_NAME: &str = "__direct";
-const MAX_OFFENSES: u8 = 5;
+/// used by the login thread to wake up the server
+const WAKER_TOKEN: Token = Token(0);
+/// time clients have to receive everything queued when the server stops
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+/// sender of operator notices, can't be confused with a client
+const OPERATOR_NAME: &str = "<server>";
 
 struct Client {
     conn: Connection,
     name: Option<String>,
-    offenses: u8,
+    /// unknown for clients that didn't connect through the network
+    addr: Option<IpAddr>,
+    /// when the client offended, oldest first
+    offenses: Vec<Instant>,
     blocked: HashSet<String>,
+    /// whether the client is logged into an account
+    registered: bool,
+    /// disconnected by an admin
+    kicked: bool,
+    /// tokens left per kind of request, see [`RateLimit`]
+    buckets: HashMap<String, Bucket>,
 }
 
 impl Client {
-    pub fn new(conn: Connection, name: Option<String>) -> Self {
+    pub fn new(conn: Connection, name: Option<String>, addr: Option<IpAddr>) -> Self {
         Self {
             conn,
             name,
-            offenses: 0,
+            addr,
+            offenses: Vec::new(),
             blocked: HashSet::new(),
+            registered: false,
+            kicked: false,
+            buckets: HashMap::new(),
         }
     }
 
-    pub fn should_remain(&self) -> bool {
+    pub fn should_remain(&self, max_offenses: u8) -> bool {
         if let Some(name) = &self.name {
-            if self.offenses >= MAX_OFFENSES {
+            if self.was_kicked(max_offenses) {
                 println!("{name} was kicked");
-            }
-            if !self.conn.alive() {
+            } else if !self.conn.alive() {
                 println!("{name} left");
             }
         }
-        self.conn.alive() && self.offenses < MAX_OFFENSES
+        self.conn.alive() && !self.too_many_offenses(max_offenses)
     }
-}
 
-#[derive(Default)]
-struct Channel {
-    name: String,
-    password: String,
-    members: HashSet<String>,
-    msg_queue: Vec<Package>,
-}
+    fn was_kicked(&self, max_offenses: u8) -> bool {
+        self.kicked || self.too_many_offenses(max_offenses)
+    }
 
-impl Channel {
-    pub fn new(name: String, password: String, founder: String) -> Self {
-        Self {
-            name,
-            password,
-            members: HashSet::from([founder]),
-            msg_queue: Vec::new(),
+    fn too_many_offenses(&self, max_offenses: u8) -> bool {
+        self.offenses.len() >= usize::from(max_offenses)
+    }
+
+    /// drop offenses older than `expiry`, unless it is zero
+    fn forget_offenses(&mut self, expiry: Duration) {
+        if !expiry.is_zero() {
+            self.offenses.retain(|since| since.elapsed() < expiry);
+        }
+    }
+
+    /// disconnect the client, telling it why
+    fn kick(&mut self, why: &str) {
+        self.conn.close_with(Response::err(why).package());
+        self.kicked = true;
+    }
+
+    /// take a token for a request with this command, if it is limited
+    fn check_rate(
+        &mut self,
+        cmd: &str,
+        limits: &HashMap<String, RateLimit>,
+    ) -> Result<(), Response> {
+        let Some(&limit) = limits.get(cmd) else {
+            return Ok(());
+        };
+        let bucket = self
+            .buckets
+            .entry(cmd.to_string())
+            .or_insert_with(|| Bucket::new(limit));
+        if bucket.take(limit) {
+            Ok(())
+        } else {
+            Err(Response::err("rate limited"))
+        }
+    }
+
+    /// the protocol version negotiated during login
+    pub fn version(&self) -> u16 {
+        self.conn.protocol().version
+    }
+
+    /// send a package in a form the client understands, see [`compatible`]
+    fn send(&mut self, pkg: impl Borrow<Package>) {
+        if let Some(pkg) = compatible(pkg.borrow(), self.conn.protocol()) {
+            self.conn.send_package(pkg);
         }
     }
+}
 
-    pub fn append_msg(&mut self, from: String, msg: String) {
-        self.msg_queue
-            .push(Response::msg(self.name.clone(), from, msg).package());
+/// adapt a package to the protocol negotiated with a client
+///
+/// Tags are only echoed to clients that support them. Legacy clients
+/// get messages without id and time, and none of the other
+/// asynchronous responses, which they don't know.
+/// Returns `None` if the package shouldn't be sent at all.
+fn compatible<'p>(pkg: &'p Package, protocol: &Protocol) -> Option<Cow<'p, Package>> {
+    let legacy = protocol.version == Protocol::LEGACY_VERSION;
+    let tags = protocol.capabilities.iter().any(|c| c == "tags");
+    if !legacy && (tags || pkg.tag.is_none()) {
+        return Some(Cow::Borrowed(pkg));
+    }
+    let mut pkg = pkg.clone();
+    if !tags {
+        pkg.tag = None;
     }
+    if legacy {
+        match pkg.cmd.as_str() {
+            "msg" => pkg.args.truncate(3),
+            "topic" | "member" | "shutdown" => return None,
+            _ => {}
+        }
+    }
+    Some(Cow::Owned(pkg))
+}
+
+/// outcome of a valid login attempt, see [`Server::check_login`]
+enum Login {
+    /// an active client with this name
+    Active(String),
+    Passive,
+    /// a password that has to be checked or hashed first, see [`HashJob`]
+    Hash {
+        name: String,
+        password: String,
+        hash: Option<String>,
+    },
 }
 
 pub struct Server {
-    login_rx: Receiver<Client>,
+    config: ServerConfig,
+    addrs: Vec<SocketAddr>,
+    poll: Poll,
+    /// wake up the server and the login thread, if any, see [`ServerHandle`]
+    wakers: Vec<Arc<Waker>>,
+    running: Arc<AtomicBool>,
+    /// token for the next connection registered with `poll`
+    next_token: usize,
+    /// hands back the connections it didn't log in yet when stopped
+    login_thread: Option<LoginThread>,
+    login_rx: Receiver<(Connection, LoginMethod, Option<IpAddr>)>,
+    /// sends its results through `login_rx`, gone once stopped
+    hasher: Option<Hasher>,
+    /// tokens left per address and kind of login request, see [`RateLimit`]
+    login_buckets: HashMap<(IpAddr, String), Bucket>,
+    /// commands typed by the operator, see [`attach_console`](Self::attach_console)
+    console_rx: Option<Receiver<Command>>,
+    /// connections handed over through [`accept`](Self::accept), waiting to log in
+    pending: Vec<(Connection, Instant)>,
     active_clients: HashMap<String, Client>,
     passive_clients: Vec<Client>,
     channels: HashMap<String, Channel>,
+    bots: HashMap<String, Bot>,
+    accounts: Accounts,
+    /// shared with the login thread
+    sanctions: Arc<Mutex<Sanctions>>,
+    pardons: Pardons,
+    last_msg_id: u64,
+    metrics: Metrics,
 }
 
 impl Server {
@@ -83,74 +231,482 @@ impl Server {
         " by blindner"
     );
 
-    pub const FEATURES: [&'static str; 4] = ["basic", "direct", "channels", "offenses"];
+    pub const FEATURES: [&'static str; 9] = [
+        "basic",
+        "direct",
+        "channels",
+        "moderation",
+        "bots",
+        "offenses",
+        "accounts",
+        "tags",
+        "admin",
+    ];
 
-    pub fn new() -> Result<Self, Error> {
-        let (tx, rx) = mpsc::channel();
-        login::login_thread(tx)?;
-        Ok(Self {
-            login_rx: rx,
-            active_clients: HashMap::new(),
-            passive_clients: Vec::new(),
-            channels: HashMap::from([(String::new(), Channel::default())]),
-        })
+    /// listen on the configured addresses
+    pub fn new(config: ServerConfig) -> Result<Self, Error> {
+        Self::builder().config(config).build()
+    }
+
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+
+    /// the addresses the server listens on, with the actual ports
+    pub fn addrs(&self) -> &[SocketAddr] {
+        &self.addrs
+    }
+
+    /// a way to stop the server from another thread
+    pub fn handle(&self) -> ServerHandle {
+        ServerHandle {
+            running: self.running.clone(),
+            wakers: self.wakers.clone(),
+        }
     }
 
-    pub fn run(&mut self) -> ! {
+    /// handle clients until stopped through a [`ServerHandle`]
+    ///
+    /// Connected clients are told that the server is shutting down.
+    pub fn run(&mut self) {
         println!("{}", Self::ABOUT);
+        for addr in &self.addrs {
+            println!("listening on {addr}");
+        }
+        for name in self.bots.keys() {
+            println!("bot {name} is running");
+        }
+        if self.console_rx.is_some() {
+            println!("enter 'help' for operator commands");
+        }
+        while self.running.load(Ordering::Relaxed) {
+            self.step(None);
+        }
+        self.shutdown("stopped by operator");
+    }
+
+    /// stop accepting clients and disconnect everyone, telling them `reason`
+    ///
+    /// Everything queued for a client is delivered first,
+    /// unless it takes longer than [`SHUTDOWN_TIMEOUT`].
+    pub fn shutdown(&mut self, reason: &str) {
+        self.handle().stop();
+        self.send_queues();
+        let notice = Response::shutdown(reason).package();
+        let mut conns: Vec<_> = self
+            .active_clients
+            .drain()
+            .map(|(_, c)| c)
+            .chain(self.passive_clients.drain(..))
+            .map(|c| c.conn)
+            .chain(self.pending.drain(..).map(|(conn, _)| conn))
+            .collect();
+        // connections that are still logging in are told as well
+        if let Some(hasher) = self.hasher.take() {
+            hasher.finish();
+        }
+        let logging_in = self
+            .login_thread
+            .take()
+            .and_then(|thread| thread.join().ok())
+            .into_iter()
+            .flatten()
+            .chain(self.login_rx.try_iter().map(|(conn, ..)| conn))
+            .collect::<Vec<_>>();
+        for mut conn in logging_in {
+            let token = self.new_token();
+            if conn.register(self.poll.registry(), token).is_ok() {
+                conns.push(conn);
+            }
+        }
+        for conn in &mut conns {
+            if let Some(notice) = compatible(&notice, conn.protocol()) {
+                conn.send_package(notice);
+            }
+        }
+        let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+        let mut events = Events::with_capacity(128);
         loop {
-            self.collect_new_clients();
-            for (client, req) in self.collect_requests() {
-                let (Ok(resp) | Err(resp)) = self.respond_to(&client, req);
-                if let Some(client) = self.active_clients.get_mut(&client) {
-                    if resp.is_bad() {
-                        client.offenses += 1;
-                    }
-                    client.conn.send_package(resp.package());
+            conns.retain_mut(|conn| {
+                conn.flush();
+                // closing with unread data would reset the connection
+                while conn.read_package().is_some() {}
+                conn.alive() && conn.queued() > 0
+            });
+            let left = deadline.saturating_duration_since(Instant::now());
+            if conns.is_empty() || left.is_zero() {
+                break;
+            }
+            if let Err(why) = poll_events(&mut self.poll, &mut events, Some(left)) {
+                eprintln!("failed to wait for clients to receive the notice: {why}");
+                break;
+            }
+        }
+        println!("server stopped");
+    }
+
+    /// handle everything that happened since the last step,
+    /// then wait up to `timeout` for something new to happen
+    ///
+    /// Use this instead of [`run`](Self::run) to drive the server
+    /// from your own loop. If waiting fails for any reason but a signal,
+    /// the error is printed and the server stops, see [`ServerHandle::is_running`].
+    pub fn step(&mut self, timeout: Option<Duration>) {
+        self.collect_new_clients();
+        self.run_console();
+        for (client, tag, req) in self.collect_requests() {
+            let (Ok(resp) | Err(resp)) = req.and_then(|req| self.respond_to(&client, req));
+            if let Some(client) = self.active_clients.get_mut(&client) {
+                if resp.is_bad() {
+                    client.offenses.push(Instant::now());
                 }
+                client.send(resp.package().with_tag(tag));
             }
+        }
+        self.send_queues();
+        self.flush_clients();
+        // announce departures right away instead of on the next wake
+        while self.prune() {
             self.send_queues();
-            self.prune();
-            thread::sleep(Duration::from_millis(5));
+            self.flush_clients();
+        }
+        // every client is checked above, so events only need to wake us up
+        let mut events = Events::with_capacity(128);
+        if let Err(why) = poll_events(&mut self.poll, &mut events, timeout) {
+            eprintln!("failed to wait for events: {why}");
+            // nothing would wake the server up anymore
+            self.handle().stop();
+        }
+    }
+
+    /// let a client connect through any transport, e.g. a [`MemoryStream`](crate::MemoryStream)
+    ///
+    /// Unlike connections accepted by the login thread, the client
+    /// logs in while the server handles its other clients.
+    pub fn accept(&mut self, transport: impl Transport + 'static) -> Result<(), Error> {
+        let mut conn: Connection = Connection::new(Box::new(transport));
+        conn.set_limits(self.config.limits());
+        let token = self.new_token();
+        conn.register(self.poll.registry(), token)?;
+        self.pending.push((conn, Instant::now()));
+        Ok(())
+    }
+
+    /// let the person running the server control it through stdin
+    ///
+    /// `reload` is called to read the config again.
+    pub fn attach_console(
+        &mut self,
+        reload: impl Fn() -> Result<ServerConfig, ConfigErr> + Send + 'static,
+    ) {
+        let (tx, rx) = mpsc::channel();
+        console::console_thread(tx, self.wakers[0].clone(), reload);
+        self.console_rx = Some(rx);
+    }
+
+    /// handle everything the operator typed since the last step
+    fn run_console(&mut self) {
+        let Some(rx) = &self.console_rx else {
+            return;
+        };
+        let cmds: Vec<_> = rx.try_iter().collect();
+        for cmd in cmds {
+            match cmd {
+                Command::Clients => self.print_clients(),
+                Command::Metrics => println!("{}", self.metrics),
+                Command::Broadcast(notice) => {
+                    let pkg = self
+                        .new_message(OPERATOR_NAME, notice)
+                        .package(GLOBAL_CHANNEL_NAME);
+                    let clients = self
+                        .active_clients
+                        .values_mut()
+                        .chain(&mut self.passive_clients);
+                    for client in clients {
+                        client.send(&pkg);
+                    }
+                }
+                Command::Kick(name) => match self.active_clients.get_mut(&name) {
+                    Some(client) => client.kick("kicked by operator"),
+                    None => println!("{name} is not online"),
+                },
+                Command::Close(channel) => self.close_channel(&channel),
+                Command::Reload(config) => self.reload(*config),
+                Command::Stop => self.handle().stop(),
+                Command::Help => println!("{}", console::HELP),
+            }
+        }
+    }
+
+    fn print_clients(&self) {
+        let mut names: Vec<_> = self.active_clients.keys().collect();
+        names.sort();
+        for name in names {
+            let client = &self.active_clients[name];
+            let mut info = vec![format!("{} offenses", client.offenses.len())];
+            if client.registered {
+                info.push("registered".to_string());
+            }
+            if self.is_admin(name) {
+                info.push("admin".to_string());
+            }
+            if self.sanctions().muted.contains(name) {
+                info.push("muted".to_string());
+            }
+            let addr = client.addr.map_or("-".to_string(), |a| a.to_string());
+            println!("{name} ({addr}): {}", info.join(", "));
         }
+        println!(
+            "{} active, {} passive clients",
+            self.active_clients.len(),
+            self.passive_clients.len()
+        );
+    }
+
+    /// remove a channel, telling its members they were kicked
+    fn close_channel(&mut self, channel: &String) {
+        if channel == GLOBAL_CHANNEL_NAME {
+            println!("the global channel can't be closed");
+            return;
+        }
+        let Some(chan) = self.channels.remove(channel) else {
+            println!("channel {channel} doesn't exist");
+            return;
+        };
+        for name in &chan.members {
+            if let Some(client) = self.active_clients.get_mut(name) {
+                client.send(Response::member(channel, name, "kick").package());
+            }
+        }
+        println!("closed channel {channel}");
+    }
+
+    /// switch to a config that was read again
+    ///
+    /// Where the server listens, TLS and storage locations
+    /// are only read at startup and stay unchanged.
+    fn reload(&mut self, mut config: ServerConfig) {
+        config.bind = std::mem::take(&mut self.config.bind);
+        config.port = self.config.port;
+        config.ipv6 = self.config.ipv6;
+        config.tls = self.config.tls.take();
+        config.accounts = self.config.accounts.take();
+        config.history_dir = self.config.history_dir.take();
+        self.sanctions().admins = config.admins.clone();
+        let clients = self
+            .active_clients
+            .values_mut()
+            .chain(&mut self.passive_clients);
+        for client in clients {
+            client.conn.set_limits(config.limits());
+        }
+        self.config = config;
+        println!("config reloaded, changing addresses, TLS or storage requires a restart");
+    }
+
+    fn new_token(&mut self) -> Token {
+        self.next_token += 1;
+        Token(self.next_token - 1)
     }
 
     fn collect_new_clients(&mut self) {
-        while let Ok(mut new_client) = self.login_rx.try_recv() {
-            if let Some(name) = &new_client.name {
-                if self.active_clients.contains_key(name) {
-                    new_client
-                        .conn
-                        .send_package(Response::err("name already used").package());
-                } else {
-                    let name = name.clone();
-                    println!("{name} has joined");
-                    new_client.conn.send_package(Response::Ack.package());
-                    self.active_clients.insert(name.clone(), new_client);
-                    self.channels
+        let mut logins = Vec::new();
+        while let Ok((mut conn, method, addr)) = self.login_rx.try_recv() {
+            let token = self.new_token();
+            if let Err(why) = conn.register(self.poll.registry(), token) {
+                println!("failed to register new client: {why}");
+                continue;
+            }
+            // the login thread doesn't know about reloads
+            conn.set_limits(self.config.limits());
+            logins.push((conn, method, addr));
+        }
+        for (mut conn, since) in std::mem::take(&mut self.pending) {
+            match login::try_login(&mut conn) {
+                Ok(method) => logins.push((conn, method, None)),
+                Err(()) if conn.alive() && since.elapsed() < login::LOGIN_TIMEOUT => {
+                    self.pending.push((conn, since))
+                }
+                Err(()) => {}
+            }
+        }
+        for (mut conn, method, addr) in logins {
+            match self.check_login(method, addr) {
+                Ok(Login::Active(name)) => {
+                    conn.send_package(Response::Ack.package());
+                    let mut client = Client::new(conn, Some(name.clone()), addr);
+                    println!("{name} has joined (protocol v{})", client.version());
+                    if self.accounts.is_registered(&name) {
+                        client.registered = true;
+                        client.blocked = self.accounts.blocked(&name);
+                        for msg in self.accounts.take_mailbox(&name) {
+                            client.send(msg);
+                        }
+                    }
+                    self.active_clients.insert(name.clone(), client);
+                    let global = self
+                        .channels
                         .get_mut(GLOBAL_CHANNEL_NAME)
-                        .expect("global channel should always exist")
-                        .members
-                        .insert(name);
+                        .expect("global channel should always exist");
+                    global.members.insert(name.clone());
+                    global.notify(&name, "join");
+                    self.trigger_bots(Event::Login(&name));
+                }
+                Ok(Login::Passive) => {
+                    conn.send_package(Response::Ack.package());
+                    self.passive_clients.push(Client::new(conn, None, addr));
+                }
+                Ok(Login::Hash {
+                    name,
+                    password,
+                    hash,
+                }) => {
+                    let Some(hasher) = &self.hasher else {
+                        continue;
+                    };
+                    // the connection comes back through `login_rx`
+                    if conn.deregister(self.poll.registry()).is_ok() {
+                        hasher.send(HashJob {
+                            conn,
+                            addr,
+                            name,
+                            password,
+                            hash,
+                        });
+                    }
+                }
+                Err(why) => conn.send_package(why.package()),
+            }
+        }
+    }
+
+    /// check whether a login attempt is valid
+    ///
+    /// Passwords are handed to the [`Hasher`] first,
+    /// its result is checked once it comes back.
+    fn check_login(
+        &mut self,
+        method: LoginMethod,
+        addr: Option<IpAddr>,
+    ) -> Result<Login, Response> {
+        let name = match method {
+            LoginMethod::Listen if self.sanctions().is_banned(None, addr) => {
+                return Err(Response::err("banned from server"))
+            }
+            LoginMethod::Listen => return Ok(Login::Passive),
+            LoginMethod::Guest(name) => {
+                if !self.config.guests {
+                    return Err(Response::err("guest logins are disabled"));
+                }
+                if self.accounts.is_registered(&name) {
+                    return Err(Response::err("name is registered"));
+                }
+                name
+            }
+            LoginMethod::Password(name, password) => {
+                self.check_login_rate("auth", addr)?;
+                if self.sanctions().is_locked(&name, addr) {
+                    return Err(Response::err("too many failed logins, try again later"));
+                }
+                let Some(hash) = self.accounts.password_hash(&name) else {
+                    self.fail_login(&name, addr);
+                    return Err(Response::err("wrong name or password"));
+                };
+                let hash = Some(hash.to_string());
+                return Ok(Login::Hash {
+                    name,
+                    password,
+                    hash,
+                });
+            }
+            LoginMethod::Verified(name, false) => {
+                self.fail_login(&name, addr);
+                return Err(Response::err("wrong name or password"));
+            }
+            LoginMethod::Verified(name, true) => {
+                self.sanctions().login_succeeded(&name);
+                name
+            }
+            LoginMethod::Register(name, password) => {
+                self.check_login_rate("register", addr)?;
+                if self.active_clients.contains_key(&name) || self.bots.contains_key(&name) {
+                    return Err(Response::err("name already used"));
+                }
+                if self.accounts.is_registered(&name) {
+                    return Err(Response::err("name is already registered"));
                 }
-            } else {
-                new_client.conn.send_package(Response::Ack.package());
-                self.passive_clients.push(new_client);
+                return Ok(Login::Hash {
+                    name,
+                    password,
+                    hash: None,
+                });
             }
+            LoginMethod::Hashed(name, hash) => {
+                if self.active_clients.contains_key(&name) || self.bots.contains_key(&name) {
+                    return Err(Response::err("name already used"));
+                }
+                // someone else may have registered it while hashing
+                if !self.accounts.register(&name, hash) {
+                    return Err(Response::err("name is already registered"));
+                }
+                name
+            }
+        };
+        if self.active_clients.contains_key(&name) || self.bots.contains_key(&name) {
+            Err(Response::err("name already used"))
+        } else if !self.is_admin(&name) && self.sanctions().is_banned(Some(&name), addr) {
+            Err(Response::err("banned from server"))
+        } else {
+            Ok(Login::Active(name))
+        }
+    }
+
+    /// take a token for a request with this command from the bucket of `addr`
+    ///
+    /// Like [`Client::check_rate`], but for clients that aren't logged in yet.
+    fn check_login_rate(&mut self, cmd: &str, addr: Option<IpAddr>) -> Result<(), Response> {
+        let (Some(&limit), Some(addr)) = (self.config.rate_limits.get(cmd), addr) else {
+            return Ok(());
+        };
+        let limits = &self.config.rate_limits;
+        // buckets that are full again behave like new ones
+        self.login_buckets
+            .retain(|(_, cmd), bucket| limits.get(cmd).is_some_and(|&l| !bucket.is_full(l)));
+        let bucket = self
+            .login_buckets
+            .entry((addr, cmd.to_string()))
+            .or_insert_with(|| Bucket::new(limit));
+        if bucket.take(limit) {
+            Ok(())
+        } else {
+            Err(Response::err("rate limited"))
         }
     }
 
-    fn collect_requests(&mut self) -> Vec<(String, Request)> {
+    /// count a failed password login, see [`Sanctions::fail_login`]
+    fn fail_login(&mut self, name: &str, addr: Option<IpAddr>) {
+        let account = self.accounts.is_registered(name).then_some(name);
+        let lock = Duration::from_secs(self.config.failed_login_lock_secs);
+        let max = self.config.max_failed_logins;
+        self.sanctions().fail_login(account, addr, max, lock);
+    }
+
+    /// read all pending requests, along with their tags
+    ///
+    /// Requests that can't be parsed or exceed the rate limit
+    /// are collected as the error to respond with.
+    fn collect_requests(&mut self) -> Vec<(String, Option<String>, Result<Request, Response>)> {
         let mut collected = Vec::new();
         for (name, client) in &mut self.active_clients {
-            while let Some(pkg) = client.conn.get_package() {
-                match Request::parse(pkg) {
-                    Ok(req) => collected.push((name.clone(), req)),
-                    Err(why) => {
-                        client.offenses += 1;
-                        client.conn.send_package(why.package())
-                    }
-                }
+            while let Some(pkg) = client.conn.read_package() {
+                let tag = pkg.as_ref().ok().and_then(|p| p.tag.clone());
+                let req = match pkg {
+                    Ok(pkg) => client
+                        .check_rate(&pkg.cmd, &self.config.rate_limits)
+                        .and_then(|()| Request::parse(pkg).map_err(Into::into)),
+                    Err(why) => Err(RequestErr::from(why).into()),
+                };
+                collected.push((name.clone(), tag, req));
             }
         }
         collected
@@ -158,53 +714,121 @@ impl Server {
 
     pub fn respond_to(&mut self, client: &String, req: Request) -> Result<Response, Response> {
         Ok(match req {
-            Request::Login(_) | Request::Listen => Response::err("already logged in"),
+            Request::Hello(_)
+            | Request::Login(_)
+            | Request::Authenticate(..)
+            | Request::Register(..)
+            | Request::Listen => Response::err("already logged in"),
             Request::Ping => Response::Ack,
             Request::Post(channel, msg) => {
+                self.check_msg_len(&msg)?;
+                self.check_muted(client)?;
                 self.get_channel(client, &channel)?
-                    .append_msg(client.clone(), msg);
+                    .check_slow_mode(client)?;
+                let msg = self.new_message(client, msg);
+                self.get_channel(client, &channel)?.append_msg(msg);
                 Response::Ack
             }
             Request::Send(to, msg) => {
-                if self.get_client(client)?.blocked.contains(&to) {
+                self.check_msg_len(&msg)?;
+                self.check_muted(client)?;
+                let own = self.active_clients.get(client);
+                if own.is_some_and(|c| c.blocked.contains(&to)) {
                     Response::err("user was blocked")
-                } else {
-                    let cl = self.get_client(&to)?;
+                } else if self.bots.contains_key(&to) {
+                    Response::err("bots can't receive messages")
+                } else if let Some(cl) = self.active_clients.get(&to) {
                     if cl.blocked.contains(client) {
                         Response::err("you were blocked by user")
                     } else {
-                        cl.conn.send_package(
-                            Response::msg(DIRECT_CHANNEL_NAME, client.clone(), msg).package(),
-                        );
+                        let pkg = self.new_message(client, msg).package(DIRECT_CHANNEL_NAME);
+                        if let Some(cl) = self.active_clients.get_mut(&to) {
+                            cl.send(pkg);
+                        }
                         Response::Ack
                     }
+                } else if !self.accounts.is_registered(&to) {
+                    Response::err("user doesn't exist")
+                } else if self.accounts.blocked(&to).contains(client) {
+                    Response::err("you were blocked by user")
+                } else if self.accounts.mailbox_full(&to, self.config.mailbox_len) {
+                    Response::err("user's mailbox is full")
+                } else {
+                    let pkg = self.new_message(client, msg).package(DIRECT_CHANNEL_NAME);
+                    self.accounts
+                        .deliver_later(&to, pkg, self.config.mailbox_len);
+                    Response::Ack
                 }
             }
             Request::Names(channel) => Response::info(&self.get_channel(client, &channel)?.members),
+            Request::History(channel, count) => {
+                let count = count.parse().map_err(|_| Response::err("invalid count"))?;
+                let chan = self.get_channel(client, &channel)?;
+                Response::info(chan.history.recent(count).flat_map(Message::info_args))
+            }
             Request::About => Response::info([Self::ABOUT]),
-            Request::Features => Response::info(Self::FEATURES),
+            Request::Features => Response::info(self.features()),
             Request::NewChannel(channel, passwd) => {
                 if self.channels.contains_key(&channel) || channel == DIRECT_CHANNEL_NAME {
                     Response::err("channel exists already")
                 } else {
+                    let history = History::new(&self.config);
                     self.channels.insert(
                         channel.clone(),
-                        Channel::new(channel, passwd, client.clone()),
+                        Channel::new(channel, passwd, client.clone(), history),
                     );
                     Response::Ack
                 }
             }
-            Request::ListChannels => Response::info(self.channels.keys()),
+            Request::ListChannels => {
+                let legacy = self
+                    .active_clients
+                    .get(client)
+                    .is_some_and(|c| c.version() == Protocol::LEGACY_VERSION);
+                if legacy {
+                    Response::info(self.channels.keys())
+                } else {
+                    Response::info(
+                        self.channels
+                            .values()
+                            .flat_map(|c| [c.name.clone(), c.topic.clone()]),
+                    )
+                }
+            }
+            Request::Topic(channel) => Response::info([&self
+                .channels
+                .get(&channel)
+                .ok_or(Response::err("channel doesn't exist"))?
+                .topic]),
+            Request::SetTopic(channel, topic) => {
+                self.check_msg_len(&topic)?;
+                self.check_muted(client)?;
+                let chan = if self.is_admin(client) {
+                    self.get_channel(client, &channel)?
+                } else {
+                    self.get_moderated(client, &channel, Role::Operator)?
+                };
+                chan.topic = topic.clone();
+                chan.msg_queue
+                    .push(Response::topic(&channel, client, topic).package());
+                Response::Ack
+            }
             Request::Subscribe(channel, passwd) => {
                 let chan = self
                     .channels
                     .get_mut(&channel)
                     .ok_or(Response::err("channel doesn't exist"))?;
-                if chan.password == passwd {
+                if chan.banned.contains(client) {
+                    Response::err("banned from channel")
+                } else if chan.password == passwd {
                     if chan.members.contains(client) {
                         Response::err("already subscribed to channel")
                     } else {
                         chan.members.insert(client.clone());
+                        chan.notify(client, "join");
+                        if !self.bots.contains_key(client) {
+                            self.trigger_bots(Event::Subscribe(&channel, client));
+                        }
                         Response::Ack
                     }
                 } else {
@@ -212,14 +836,106 @@ impl Server {
                 }
             }
             Request::Unsubscribe(channel) => {
-                self.get_channel(client, &channel)?
-                    .members
-                    .retain(|n| n != client);
+                let registered = self
+                    .channels
+                    .get(&channel)
+                    .is_some_and(|c| self.accounts.is_registered(&c.founder));
+                let chan = self.get_channel(client, &channel)?;
+                chan.notify(client, "leave");
+                chan.remove_member(client);
+                chan.check_founder(registered);
+                Response::Ack
+            }
+            Request::Kick(channel, name) => {
+                let chan = self.get_moderated(client, &channel, Role::Operator)?;
+                match chan.role(&name) {
+                    None => Response::err("user is not subscribed to channel"),
+                    Some(role) if chan.role(client) <= Some(role) => {
+                        Response::err("insufficient permissions")
+                    }
+                    Some(_) => {
+                        chan.remove_member(&name);
+                        self.notify_removed(&channel, &name, "kick");
+                        Response::Ack
+                    }
+                }
+            }
+            Request::Ban(channel, name) => {
+                let chan = self.get_moderated(client, &channel, Role::Operator)?;
+                if chan.role(&name) >= chan.role(client) || name == chan.founder {
+                    Response::err("insufficient permissions")
+                } else if chan.banned.insert(name.clone()) {
+                    let was_member = chan.members.contains(&name);
+                    chan.remove_member(&name);
+                    if was_member {
+                        self.notify_removed(&channel, &name, "ban");
+                    }
+                    Response::Ack
+                } else {
+                    Response::err("user is already banned")
+                }
+            }
+            Request::Unban(channel, name) => {
+                let chan = self.get_moderated(client, &channel, Role::Operator)?;
+                if chan.banned.remove(&name) {
+                    Response::Ack
+                } else {
+                    Response::err("user is not banned")
+                }
+            }
+            Request::Promote(channel, name) => {
+                let chan = self.get_moderated(client, &channel, Role::Operator)?;
+                match chan.role(&name) {
+                    None => Response::err("user is not subscribed to channel"),
+                    Some(Role::Member) => {
+                        chan.operators.insert(name);
+                        Response::Ack
+                    }
+                    Some(_) => Response::err("user is already an operator"),
+                }
+            }
+            Request::Demote(channel, name) => {
+                let chan = self.get_moderated(client, &channel, Role::Founder)?;
+                if chan.role(&name) == Some(Role::Operator) {
+                    chan.operators.remove(&name);
+                    Response::Ack
+                } else {
+                    Response::err("user is not an operator")
+                }
+            }
+            Request::Handover(channel, name) => {
+                let chan = self.get_moderated(client, &channel, Role::Founder)?;
+                match chan.role(&name) {
+                    None => Response::err("user is not subscribed to channel"),
+                    Some(Role::Founder) => Response::err("user is already the founder"),
+                    Some(_) => {
+                        chan.operators.remove(&name);
+                        chan.operators.insert(client.clone());
+                        chan.founder = name;
+                        Response::Ack
+                    }
+                }
+            }
+            Request::ChannelPassword(channel, passwd) => {
+                self.get_moderated(client, &channel, Role::Operator)?
+                    .password = passwd;
+                Response::Ack
+            }
+            Request::SlowMode(channel, secs) => {
+                let slow_mode = Self::parse_duration(&secs)?;
+                let chan = self.get_moderated(client, &channel, Role::Operator)?;
+                chan.slow_mode = slow_mode;
+                chan.last_post.clear();
                 Response::Ack
             }
             Request::Block(name) => {
-                self.get_client(&name)?;
-                if self.get_client(client)?.blocked.insert(name) {
+                // bots are listed in `names` like everyone else
+                if !self.accounts.is_registered(&name) && !self.bots.contains_key(&name) {
+                    self.get_client(&name)?;
+                }
+                let cl = self.get_client(client)?;
+                if cl.blocked.insert(name) {
+                    self.save_blocked(client);
                     Response::Ack
                 } else {
                     Response::err("user already blocked")
@@ -227,27 +943,216 @@ impl Server {
             }
             Request::Unblock(name) => {
                 if self.get_client(client)?.blocked.remove(&name) {
+                    self.save_blocked(client);
                     Response::Ack
                 } else {
                     Response::err("user wasn't blocked")
                 }
             }
-            Request::Offenses => Response::info([
-                self.get_client(client)?.offenses.to_string(),
-                MAX_OFFENSES.to_string(),
-            ]),
+            Request::Offenses => {
+                // offenses are otherwise only expired after handling requests
+                let expiry = Duration::from_secs(self.config.offense_expiry_secs);
+                let cl = self.get_client(client)?;
+                cl.forget_offenses(expiry);
+                Response::info([
+                    cl.offenses.len().to_string(),
+                    self.config.max_offenses.to_string(),
+                ])
+            }
             Request::Pardon(name) => {
+                if &name == client {
+                    return Err(Response::err("can't pardon yourself"));
+                }
+                let window = Duration::from_secs(self.config.pardon_window_secs);
+                // guests by address, as they could just pick another name
+                let cl = self.get_client(client)?;
+                let key = match cl.addr {
+                    Some(addr) if !cl.registered => addr.to_string(),
+                    _ => client.clone(),
+                };
+                let admin = self.is_admin(client);
+                let limited = !admin && self.pardons.count(&key, window) >= self.config.max_pardons;
+                let expiry = Duration::from_secs(self.config.offense_expiry_secs);
                 let cl = self.get_client(&name)?;
-                if cl.offenses > 0 {
-                    cl.offenses -= 1;
+                cl.forget_offenses(expiry);
+                if limited {
+                    Response::err("too many pardons, try again later")
+                } else if cl.offenses.pop().is_some() {
+                    self.pardons.record(client, &name);
+                    if !admin {
+                        self.pardons.charge(&key, window);
+                    }
                     Response::Ack
                 } else {
                     Response::err("user has no offenses")
                 }
             }
+            Request::PardonLog => {
+                self.check_admin(client)?;
+                Response::info(self.pardons.info_args())
+            }
+            Request::ServerKick(name) => {
+                self.check_admin(client)?;
+                if self.is_admin(&name) {
+                    return Err(Response::err("user is an admin"));
+                }
+                self.active_clients
+                    .get_mut(&name)
+                    .ok_or(Response::err("user is not online"))?
+                    .kick("kicked by admin");
+                Response::Ack
+            }
+            Request::ServerBan(target, duration) => {
+                self.check_admin(client)?;
+                let duration = Self::parse_duration(&duration)?;
+                if let Ok(addr) = target.parse::<IpAddr>() {
+                    let was_banned = self.sanctions().banned_addrs.set(addr, duration);
+                    if duration.is_zero() && !was_banned {
+                        return Err(Response::err("address is not banned"));
+                    }
+                    let admins: HashSet<_> = self
+                        .active_clients
+                        .keys()
+                        .filter(|name| self.is_admin(name))
+                        .cloned()
+                        .collect();
+                    let clients = self
+                        .active_clients
+                        .iter_mut()
+                        .filter(|(name, _)| !admins.contains(*name))
+                        .map(|(_, c)| c)
+                        .chain(&mut self.passive_clients)
+                        .filter(|c| c.addr == Some(addr));
+                    for client in clients {
+                        client.kick("banned from server");
+                    }
+                } else {
+                    if self.is_admin(&target) {
+                        return Err(Response::err("user is an admin"));
+                    }
+                    let was_banned = self.sanctions().banned_names.set(target.clone(), duration);
+                    if duration.is_zero() && !was_banned {
+                        return Err(Response::err("user is not banned"));
+                    }
+                    if let Some(client) = self.active_clients.get_mut(&target) {
+                        client.kick("banned from server");
+                    }
+                }
+                Response::Ack
+            }
+            Request::Mute(name, duration) => {
+                self.check_admin(client)?;
+                let duration = Self::parse_duration(&duration)?;
+                if !self.active_clients.contains_key(&name) && !self.accounts.is_registered(&name) {
+                    return Err(Response::err("user doesn't exist"));
+                }
+                if self.is_admin(&name) {
+                    return Err(Response::err("user is an admin"));
+                }
+                if !self.sanctions().muted.set(name, duration) && duration.is_zero() {
+                    return Err(Response::err("user is not muted"));
+                }
+                Response::Ack
+            }
         })
     }
 
+    /// whether `name` is configured as admin and logged in with its password
+    ///
+    /// Offline users are admins as long as they have an account,
+    /// as nobody else can log in with their name.
+    fn is_admin(&self, name: &String) -> bool {
+        self.config.admins.contains(name)
+            && self
+                .active_clients
+                .get(name)
+                .map_or(self.accounts.is_registered(name), |c| c.registered)
+    }
+
+    fn sanctions(&self) -> MutexGuard<'_, Sanctions> {
+        self.sanctions.lock().expect("login thread panicked")
+    }
+
+    fn check_admin(&self, client: &String) -> Result<(), Response> {
+        if self.is_admin(client) {
+            Ok(())
+        } else {
+            Err(Response::err("not an admin"))
+        }
+    }
+
+    fn check_muted(&self, client: &String) -> Result<(), Response> {
+        if self.sanctions().muted.contains(client) {
+            Err(Response::err("you are muted"))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn parse_duration(secs: &str) -> Result<Duration, Response> {
+        secs.parse()
+            .map(Duration::from_secs)
+            .map_err(|_| Response::err("invalid duration"))
+    }
+
+    fn check_msg_len(&self, msg: &str) -> Result<(), Response> {
+        if msg.chars().count() > self.config.max_msg_len {
+            Err(Response::err(format!(
+                "message exceeds {} characters",
+                self.config.max_msg_len
+            )))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn new_message(&mut self, from: &str, msg: String) -> Message {
+        self.last_msg_id += 1;
+        Message::new(self.last_msg_id, from.to_string(), msg)
+    }
+
+    /// persist the blocked users of a registered client
+    fn save_blocked(&mut self, client: &String) {
+        if let Some(cl) = self.active_clients.get(client).filter(|c| c.registered) {
+            self.accounts.set_blocked(client, cl.blocked.clone());
+        }
+    }
+
+    fn features(&self) -> Vec<&'static str> {
+        let mut features = Vec::from(Self::FEATURES);
+        if self.config.guests {
+            features.push("guests");
+        }
+        features
+    }
+
+    /// let all bots react to an event
+    ///
+    /// The commands of a trigger are executed in order, as if the
+    /// bot had sent them as requests. Unless the trigger ignores errors,
+    /// the first failing command aborts the remaining ones.
+    fn trigger_bots(&mut self, event: Event) {
+        let triggered: Vec<_> = self
+            .bots
+            .values()
+            .filter_map(|bot| Some((bot.name.clone(), bot.triggered(&event)?)))
+            .collect();
+        for (bot, trigger) in triggered {
+            for pkg in trigger.cmds {
+                let (Ok(resp) | Err(resp)) = match Request::parse(pkg) {
+                    Ok(req) => self.respond_to(&bot, req),
+                    Err(why) => Err(why.into()),
+                };
+                if let Response::Err(why) = resp {
+                    if !trigger.ignore_errors {
+                        println!("bot {bot} failed: {why}");
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
     fn get_channel(&mut self, client: &String, channel: &String) -> Result<&mut Channel, Response> {
         let chan = self
             .channels
@@ -259,6 +1164,37 @@ impl Server {
             .ok_or(Response::err("not subscribed to channel"))
     }
 
+    /// get a channel the client has at least the given role in
+    fn get_moderated(
+        &mut self,
+        client: &String,
+        channel: &String,
+        role: Role,
+    ) -> Result<&mut Channel, Response> {
+        let chan = self.get_channel(client, channel)?;
+        if chan.role(client) >= Some(role) {
+            Ok(chan)
+        } else if role == Role::Founder {
+            Err(Response::err("not the founder of channel"))
+        } else {
+            Err(Response::err("not an operator of channel"))
+        }
+    }
+
+    /// announce that `name` was removed from a channel
+    ///
+    /// Unlike the remaining members, the removed client
+    /// is told directly, as it no longer receives the channel's queue.
+    fn notify_removed(&mut self, channel: &String, name: &String, event: &str) {
+        let notification = Response::member(channel, name, event).package();
+        if let Some(chan) = self.channels.get_mut(channel) {
+            chan.msg_queue.push(notification.clone());
+        }
+        if let Some(client) = self.active_clients.get_mut(name) {
+            client.send(notification);
+        }
+    }
+
     fn get_client(&mut self, client: &String) -> Result<&mut Client, Response> {
         self.active_clients
             .get_mut(client)
@@ -270,24 +1206,391 @@ impl Server {
             for msg in channel.msg_queue.drain(..) {
                 for name in &channel.members {
                     if let Some(client) = self.active_clients.get_mut(name) {
-                        client.conn.send_package(&msg);
+                        client.send(&msg);
                     }
                 }
                 if channel.name == GLOBAL_CHANNEL_NAME {
                     for client in &mut self.passive_clients {
-                        client.conn.send_package(&msg);
+                        client.send(&msg);
                     }
                 }
             }
         }
     }
 
-    fn prune(&mut self) {
-        self.active_clients.retain(|_, c| c.should_remain());
+    /// send queued data and disconnect clients that can't keep up
+    fn flush_clients(&mut self) {
+        let max = self.config.max_queued_bytes;
+        let clients = self
+            .active_clients
+            .values_mut()
+            .chain(&mut self.passive_clients);
+        for client in clients {
+            client.conn.flush();
+            if client.conn.queued() > max {
+                let name = client.name.as_deref().unwrap_or("passive client");
+                println!("{name} was too slow, {} bytes queued", client.conn.queued());
+                client
+                    .conn
+                    .close_with(Response::err("disconnected for reading too slowly").package());
+                self.metrics.slow_disconnects += 1;
+            }
+        }
+        self.metrics.record_queues(
+            self.active_clients
+                .values()
+                .chain(&self.passive_clients)
+                .map(|c| c.conn.queued()),
+        );
+    }
+
+    /// statistics about data waiting to be sent
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// remove clients that left or were kicked
+    ///
+    /// Returns whether any active client departed.
+    fn prune(&mut self) -> bool {
+        let max_offenses = self.config.max_offenses;
+        let expiry = Duration::from_secs(self.config.offense_expiry_secs);
+        let mut departed = HashMap::new();
+        let mut offenders = Vec::new();
+        self.active_clients.retain(|name, c| {
+            c.forget_offenses(expiry);
+            let remain = c.should_remain(max_offenses);
+            if !remain {
+                let event = if c.was_kicked(max_offenses) {
+                    "kick"
+                } else {
+                    "leave"
+                };
+                departed.insert(name.clone(), event);
+                if c.too_many_offenses(max_offenses) {
+                    offenders.push((name.clone(), c.addr));
+                }
+            }
+            remain
+        });
+        let ban = Duration::from_secs(self.config.offense_ban_secs);
+        if !ban.is_zero() && !offenders.is_empty() {
+            let mut sanctions = self.sanctions();
+            for (name, addr) in offenders {
+                sanctions.banned_names.set(name, ban);
+                if let Some(addr) = addr {
+                    sanctions.banned_addrs.set(addr, ban);
+                }
+            }
+        }
         self.passive_clients.retain(|c| c.conn.alive());
         self.channels.retain(|_, c| {
-            c.members.retain(|n| self.active_clients.contains_key(n));
+            for (name, event) in &departed {
+                if c.members.contains(name) {
+                    c.notify(name, event);
+                }
+            }
+            c.members
+                .retain(|n| self.active_clients.contains_key(n) || self.bots.contains_key(n));
+            c.operators.retain(|n| c.members.contains(n));
+            c.check_founder(self.accounts.is_registered(&c.founder));
             c.name == GLOBAL_CHANNEL_NAME || !c.members.is_empty()
         });
+        !departed.is_empty()
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        // otherwise, the login thread would keep the ports open
+        self.handle().stop();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transport::MemoryStream;
+
+    fn server() -> Server {
+        Server::builder()
+            .config(ServerConfig {
+                accounts: None,
+                ..Default::default()
+            })
+            .without_listeners()
+            .build()
+            .unwrap()
+    }
+
+    /// connect a client through memory and log it in, speaking the newest protocol
+    fn login(server: &mut Server, name: &str) -> Connection<MemoryStream> {
+        let (local, remote) = MemoryStream::pair();
+        server.accept(remote).unwrap();
+        let mut conn = Connection::new(local);
+        conn.send_package(hello());
+        conn.send_package(Request::login(name).package());
+        server.step(Some(Duration::ZERO));
+        assert!(matches!(
+            Response::try_from(conn.get_package().unwrap()),
+            Ok(Response::Info(_))
+        ));
+        conn
+    }
+
+    /// offer everything this version knows
+    fn hello() -> Package {
+        let protocol = Protocol {
+            version: Protocol::VERSION,
+            capabilities: Server::FEATURES.map(String::from).to_vec(),
+        };
+        Request::hello(protocol.args()).package()
+    }
+
+    /// all responses that arrived so far
+    fn responses(conn: &mut Connection<MemoryStream>) -> Vec<Response> {
+        std::iter::from_fn(|| conn.get_package())
+            .map(|pkg| Response::try_from(pkg).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn bots_start_without_run() {
+        let server = server();
+        assert!(server.addrs().is_empty());
+        assert!(server.channels["printf"].members.contains("default_bot"));
+    }
+
+    #[test]
+    fn bot_triggers() {
+        let mut server = server();
+        let mut alice = login(&mut server, "alice");
+        let from_bot = |resps: Vec<Response>, channel: &str, text: &str| {
+            resps.iter().any(|resp| {
+                matches!(resp, Response::Msg(chan, name, msg, ..)
+                    if chan == channel && name == "default_bot" && msg == text)
+            })
+        };
+        assert!(from_bot(
+            responses(&mut alice),
+            DIRECT_CHANNEL_NAME,
+            "Welcome back, alice!"
+        ));
+        alice.send_package(Request::subscribe("printf", "guenni").package());
+        server.step(Some(Duration::ZERO));
+        assert!(from_bot(
+            responses(&mut alice),
+            "printf",
+            "A wild alice has appeared!"
+        ));
+        alice.send_package(Request::block("default_bot").package());
+        server.step(Some(Duration::ZERO));
+        assert!(matches!(responses(&mut alice)[..], [Response::Ack]));
+    }
+
+    #[test]
+    fn memory_clients() {
+        let mut server = server();
+        let mut alice = login(&mut server, "alice");
+        assert!(matches!(responses(&mut alice)[0], Response::Ack));
+        let mut bob = login(&mut server, "bob");
+        assert!(matches!(responses(&mut bob)[0], Response::Ack));
+        assert!(responses(&mut alice).iter().any(
+            |r| matches!(r, Response::Member(_, name, event) if name == "bob" && event == "join")
+        ));
+
+        alice.send_package(Request::post("", "hi").package());
+        server.step(Some(Duration::ZERO));
+        assert!(matches!(responses(&mut alice)[0], Response::Ack));
+        assert!(responses(&mut bob).iter().any(
+            |r| matches!(r, Response::Msg(_, name, msg, ..) if name == "alice" && msg == "hi")
+        ));
+
+        let mut duplicate = login(&mut server, "bob");
+        assert!(matches!(&responses(&mut duplicate)[..], [Response::Err(_)]));
+
+        drop(bob);
+        server.step(Some(Duration::ZERO));
+        assert!(!server.active_clients.contains_key("bob"));
+        assert!(responses(&mut alice).iter().any(
+            |r| matches!(r, Response::Member(_, name, event) if name == "bob" && event == "leave")
+        ));
+    }
+
+    #[test]
+    fn expired_offenses() {
+        let mut server = server();
+        let mut alice = login(&mut server, "alice");
+        let mut bob = login(&mut server, "bob");
+        responses(&mut alice);
+        responses(&mut bob);
+        let expiry = Duration::from_secs(server.config.offense_expiry_secs);
+        let expired = Instant::now() - expiry - Duration::from_secs(1);
+        server.active_clients.get_mut("alice").unwrap().offenses = vec![expired; 2];
+
+        alice.send_package(Request::Offenses.package());
+        bob.send_package(Request::pardon("alice").package());
+        server.step(Some(Duration::ZERO));
+        let max = server.config.max_offenses.to_string();
+        assert!(
+            matches!(&responses(&mut alice)[..], [Response::Info(args)] if args == &["0", &max])
+        );
+        assert!(matches!(
+            &responses(&mut bob)[..],
+            [Response::Err(why)] if why == "user has no offenses"
+        ));
+    }
+
+    #[test]
+    fn passwords_off_the_event_loop() {
+        let mut server = server();
+        let (local, remote) = MemoryStream::pair();
+        server.accept(remote).unwrap();
+        let mut alice = Connection::new(local);
+        alice.send_package(hello());
+        alice.send_package(Request::register("alice", "secret").package());
+        server.step(Some(Duration::ZERO));
+        // hashing happens on another thread, the result is collected later
+        assert!(matches!(responses(&mut alice)[..], [Response::Info(_)]));
+        assert!(!server.active_clients.contains_key("alice"));
+        while !server.active_clients.contains_key("alice") {
+            server.step(Some(Duration::from_millis(10)));
+        }
+        assert!(matches!(responses(&mut alice)[0], Response::Ack));
+        assert!(server.accounts.is_registered("alice"));
+    }
+
+    #[test]
+    fn hasher_queue_is_bounded() {
+        let mut server = server();
+        let mut conns: Vec<_> = (0..login::HASH_QUEUE_LEN * 2)
+            .map(|i| {
+                let (local, remote) = MemoryStream::pair();
+                server.accept(remote).unwrap();
+                let mut conn = Connection::new(local);
+                conn.send_package(Request::register(format!("user{i}"), "secret").package());
+                conn
+            })
+            .collect();
+        server.step(Some(Duration::ZERO));
+        let busy = conns
+            .iter_mut()
+            .flat_map(responses)
+            .filter(|r| matches!(r, Response::Err(why) if why == "server busy, try again later"))
+            .count();
+        assert!(busy >= login::HASH_QUEUE_LEN - 1);
+    }
+
+    #[test]
+    fn console() {
+        let mut server = server();
+        let (tx, rx) = mpsc::channel();
+        server.console_rx = Some(rx);
+        let mut alice = login(&mut server, "alice");
+        let mut bob = login(&mut server, "bob");
+        alice.send_package(Request::new_channel("rust", "").package());
+        server.step(Some(Duration::ZERO));
+        responses(&mut alice);
+        responses(&mut bob);
+
+        tx.send(Command::Broadcast("hello".to_string())).unwrap();
+        tx.send(Command::Close("rust".to_string())).unwrap();
+        tx.send(Command::Close(GLOBAL_CHANNEL_NAME.to_string()))
+            .unwrap();
+        server.step(Some(Duration::ZERO));
+        assert!(!server.channels.contains_key("rust"));
+        assert!(server.channels.contains_key(GLOBAL_CHANNEL_NAME));
+        assert!(matches!(
+            &responses(&mut alice)[..],
+            [Response::Msg(_, name, msg, ..), Response::Member(chan, _, event)]
+                if name == OPERATOR_NAME && msg == "hello" && chan == "rust" && event == "kick"
+        ));
+        assert!(matches!(&responses(&mut bob)[..], [Response::Msg(..)]));
+
+        tx.send(Command::Kick("bob".to_string())).unwrap();
+        tx.send(Command::Reload(Box::new(ServerConfig {
+            max_msg_len: 3,
+            port: 1,
+            ..Default::default()
+        })))
+        .unwrap();
+        server.step(Some(Duration::ZERO));
+        assert!(!server.active_clients.contains_key("bob"));
+        assert_eq!(server.config.max_msg_len, 3);
+        assert_eq!(server.config.port, crate::SERVER_PORT);
+        assert!(server.config.accounts.is_none());
+
+        tx.send(Command::Stop).unwrap();
+        server.step(Some(Duration::ZERO));
+        assert!(!server.handle().is_running());
+    }
+
+    #[test]
+    fn legacy_clients() {
+        let mut server = server();
+        let mut alice = login(&mut server, "alice");
+        let (local, remote) = MemoryStream::pair();
+        server.accept(remote).unwrap();
+        let mut bob = Connection::new(local);
+        bob.send_package(Request::login("bob").package());
+        bob.send_package(Request::Ping.package().with_tag(Some("1".to_string())));
+        server.step(Some(Duration::ZERO));
+        alice.send_package(Request::post("", "hi").package());
+        server.step(Some(Duration::ZERO));
+        // no tags, no member events, and messages without id and time
+        let received: Vec<_> = std::iter::from_fn(|| bob.get_package()).collect();
+        assert!(received.iter().all(|pkg| pkg.tag.is_none()));
+        assert!(received.iter().all(|pkg| pkg.cmd != "member"));
+        assert_eq!(received.last().unwrap().args, ["", "alice", "hi"]);
+        assert!(matches!(
+            &responses(&mut alice)[..],
+            [.., Response::Member(_, name, _), Response::Ack, Response::Msg(..)] if name == "bob"
+        ));
+    }
+
+    #[test]
+    fn shutdown() {
+        // the login thread is told to stop as well
+        let mut server = Server::new(ServerConfig {
+            port: 0,
+            accounts: None,
+            ..Default::default()
+        })
+        .unwrap();
+        let mut alice = login(&mut server, "alice");
+        let mut bob = login(&mut server, "bob");
+        responses(&mut alice);
+        alice.send_package(Request::post("", "bye").package());
+        server.step(Some(Duration::ZERO));
+        // neither logged in through memory nor through the login thread
+        let (local, remote) = MemoryStream::pair();
+        server.accept(remote).unwrap();
+        let mut carol = Connection::new(local);
+        carol.send_package(hello());
+        server.step(Some(Duration::ZERO));
+        let mut dave = Connection::to(server.addrs[0]).unwrap();
+        dave.send_package(hello());
+        assert!(matches!(
+            Response::try_from(dave.wait_package().unwrap()),
+            Ok(Response::Info(_))
+        ));
+        server.shutdown("maintenance");
+        assert!(!server.handle().is_running());
+        assert!(server.active_clients.is_empty());
+        // queued messages are delivered before the notice
+        assert!(matches!(
+            &responses(&mut bob)[..],
+            [.., Response::Msg(_, _, msg, ..), Response::Shutdown(why)]
+                if msg == "bye" && why == "maintenance"
+        ));
+        assert!(bob.wait_package().is_none());
+        assert!(matches!(
+            &responses(&mut carol)[..],
+            [Response::Info(_), Response::Shutdown(why)] if why == "maintenance"
+        ));
+        assert!(matches!(
+            Response::try_from(dave.wait_package().unwrap()),
+            Ok(Response::Shutdown(why)) if why == "maintenance"
+        ));
     }
 }
@@ -0,0 +1,206 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::Error,
+    path::PathBuf,
+};
+
+use argon2::{
+    password_hash::{rand_core::OsRng, SaltString},
+    Argon2, PasswordHash, PasswordHasher, PasswordVerifier,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{package::Package, response::Response};
+
+#[derive(Serialize, Deserialize)]
+struct Account {
+    /// argon2 hash of the password in PHC string format
+    hash: String,
+    /// users this account has blocked
+    #[serde(default)]
+    blocked: HashSet<String>,
+    /// direct messages received while offline
+    #[serde(default)]
+    mailbox: Vec<Package>,
+}
+
+/// Registered users, optionally persisted as JSON
+///
+/// Passwords are never stored, only their salted argon2 hashes.
+#[derive(Default)]
+pub struct Accounts {
+    path: Option<PathBuf>,
+    accounts: HashMap<String, Account>,
+}
+
+impl Accounts {
+    /// load accounts from `path`
+    ///
+    /// A missing file is treated as empty. Without a path,
+    /// accounts are only kept in memory.
+    pub fn load(path: Option<PathBuf>) -> Result<Self, Error> {
+        let accounts = match &path {
+            Some(path) if path.exists() => serde_json::from_str(&std::fs::read_to_string(path)?)?,
+            _ => HashMap::new(),
+        };
+        Ok(Self { path, accounts })
+    }
+
+    pub fn is_registered(&self, name: &str) -> bool {
+        self.accounts.contains_key(name)
+    }
+
+    /// the stored hash of the password of account `name`
+    pub fn password_hash(&self, name: &str) -> Option<&str> {
+        self.accounts.get(name).map(|a| a.hash.as_str())
+    }
+
+    /// create a new account with a hash from [`hash_password`]
+    ///
+    /// Returns `false` if the name is already registered.
+    pub fn register(&mut self, name: &str, hash: String) -> bool {
+        if self.is_registered(name) {
+            return false;
+        }
+        self.accounts.insert(
+            name.to_string(),
+            Account {
+                hash,
+                blocked: HashSet::new(),
+                mailbox: Vec::new(),
+            },
+        );
+        self.save();
+        true
+    }
+
+    /// users blocked by account `name`
+    pub fn blocked(&self, name: &str) -> HashSet<String> {
+        self.accounts
+            .get(name)
+            .map(|a| a.blocked.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn set_blocked(&mut self, name: &str, blocked: HashSet<String>) {
+        if let Some(account) = self.accounts.get_mut(name) {
+            account.blocked = blocked;
+            self.save();
+        }
+    }
+
+    /// whether the mailbox of `name` can't take another message
+    pub fn mailbox_full(&self, name: &str, max_len: usize) -> bool {
+        self.accounts
+            .get(name)
+            .is_some_and(|account| account.mailbox.len() >= max_len)
+    }
+
+    /// store a direct message until `name` logs in again
+    ///
+    /// Returns `false` if the mailbox already holds `max_len` messages.
+    pub fn deliver_later(&mut self, name: &str, msg: Package, max_len: usize) -> bool {
+        let Some(account) = self.accounts.get_mut(name) else {
+            return false;
+        };
+        if account.mailbox.len() >= max_len {
+            return false;
+        }
+        account.mailbox.push(msg);
+        self.save();
+        true
+    }
+
+    /// highest id of the messages waiting in any mailbox
+    pub fn last_msg_id(&self) -> Option<u64> {
+        self.accounts
+            .values()
+            .flat_map(|account| &account.mailbox)
+            .filter_map(|pkg| match Response::try_from(pkg.clone()) {
+                Ok(Response::Msg(_, _, _, id, _)) => id.parse().ok(),
+                _ => None,
+            })
+            .max()
+    }
+
+    /// remove all stored messages of `name`
+    pub fn take_mailbox(&mut self, name: &str) -> Vec<Package> {
+        let Some(account) = self.accounts.get_mut(name) else {
+            return Vec::new();
+        };
+        let mailbox = std::mem::take(&mut account.mailbox);
+        if !mailbox.is_empty() {
+            self.save();
+        }
+        mailbox
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let json = serde_json::to_string_pretty(&self.accounts).expect("accounts should serialize");
+        if let Err(why) = std::fs::write(path, json) {
+            eprintln!("failed to save accounts: {why}");
+        }
+    }
+}
+
+/// salt and hash `password` for a new account
+///
+/// This takes a while on purpose, so better not call it on the event loop.
+pub fn hash_password(password: &str) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .expect("default argon2 parameters should be valid")
+        .to_string()
+}
+
+/// check whether `password` matches a hash from [`hash_password`]
+///
+/// As slow as hashing, see there.
+pub fn verify_password(hash: &str, password: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|hash| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn passwords() {
+        let mut accounts = Accounts::default();
+        assert!(accounts.register("me", hash_password("secret")));
+        assert!(!accounts.register("me", hash_password("other")));
+        let hash = accounts.password_hash("me").unwrap();
+        assert!(verify_password(hash, "secret"));
+        assert!(!verify_password(hash, "Secret"));
+        assert!(!hash.contains("secret"));
+        assert!(accounts.password_hash("you").is_none());
+        assert!(!verify_password("", "secret"));
+    }
+
+    #[test]
+    fn mailbox() {
+        let mut accounts = Accounts::default();
+        accounts.register("me", hash_password("secret"));
+        assert!(!accounts.deliver_later("you", Package::default(), 2));
+        assert!(accounts.deliver_later("me", Package::default(), 2));
+        assert!(accounts.deliver_later("me", Package::default(), 2));
+        assert!(!accounts.deliver_later("me", Package::default(), 2));
+        assert!(accounts.mailbox_full("me", 2));
+        assert_eq!(accounts.last_msg_id(), None);
+        assert_eq!(accounts.take_mailbox("me").len(), 2);
+        assert!(!accounts.mailbox_full("me", 2));
+        let msg = Response::msg("", "you", "hi", "42", "0").package();
+        accounts.deliver_later("me", msg, 2);
+        assert_eq!(accounts.last_msg_id(), Some(42));
+        accounts.take_mailbox("me");
+        assert!(accounts.take_mailbox("me").is_empty());
+    }
+}
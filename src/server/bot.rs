@@ -1,25 +1,119 @@
-use std::{collections::HashMap, sync::{mpsc::Receiver, Arc, Mutex}, thread};
+use std::collections::HashMap;
 
 use serde::{Deserialize, Serialize};
 
-use crate::Package;
+use crate::package::Package;
 
 pub const DEFAULT_BOT_JSON: &str = include_str!("default_bot.json");
 
+/// A bot running inside the server
+///
+/// Bots don't have a connection. Instead, they are members of
+/// channels just like regular clients and execute the commands
+/// of their [`Trigger`]s whenever a matching [`Event`] occurs.
 #[derive(Serialize, Deserialize)]
 pub struct Bot {
-    name: String,
+    pub name: String,
     key: String,
     trigger: HashMap<String, Trigger>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Trigger {
-    ignore_errors: bool,
-    cmds: Vec<Package>,
+    pub ignore_errors: bool,
+    pub cmds: Vec<Package>,
+}
+
+/// Things happening on the server that bots can react to
+///
+/// The trigger key for an event is its name, followed by
+/// its arguments in parentheses (if any), e.g. `subscribe(printf)`.
+/// An argument may be replaced with `*` to match anything.
+pub enum Event<'e> {
+    Startup,
+    /// a client with the given name logged in
+    Login(&'e str),
+    /// a client subscribed to a channel
+    ///
+    /// arguments are `channel` and `name`
+    Subscribe(&'e str, &'e str),
 }
 
-pub type BotList = Arc<Mutex<HashMap<String, Bot>>>;
+impl Event<'_> {
+    fn name(&self) -> &'static str {
+        match self {
+            Event::Startup => "startup",
+            Event::Login(_) => "login",
+            Event::Subscribe(..) => "subscribe",
+        }
+    }
+
+    /// arguments that have to be matched by the trigger key
+    fn args(&self) -> Vec<&str> {
+        match self {
+            Event::Startup | Event::Login(_) => Vec::new(),
+            Event::Subscribe(channel, _) => vec![channel],
+        }
+    }
+
+    /// variables available as `${..}` in trigger commands
+    fn vars(&self) -> Vec<(&'static str, &str)> {
+        match self {
+            Event::Startup => Vec::new(),
+            Event::Login(name) => vec![("name", name)],
+            Event::Subscribe(channel, name) => vec![("channel", channel), ("name", name)],
+        }
+    }
+
+    fn matches(&self, key: &str) -> bool {
+        let (name, args) = match key.split_once('(') {
+            Some((name, args)) => {
+                let Some(args) = args.strip_suffix(')') else {
+                    return false;
+                };
+                (name, args.split(',').map(str::trim).collect())
+            }
+            None => (key, Vec::new()),
+        };
+        let own_args = self.args();
+        name.trim() == self.name()
+            && args.len() == own_args.len()
+            && args.iter().zip(own_args).all(|(a, o)| *a == "*" || *a == o)
+    }
+}
+
+impl Bot {
+    /// get the trigger reacting to `event`, with all variables substituted
+    ///
+    /// If several triggers match, the one with the fewest `*` is chosen,
+    /// e.g. `subscribe(printf)` over `subscribe(*)`.
+    pub fn triggered(&self, event: &Event) -> Option<Trigger> {
+        let (_, trigger) = self
+            .trigger
+            .iter()
+            .filter(|(key, _)| event.matches(key))
+            // the key only breaks ties to stay the same between runs
+            .min_by_key(|(key, _)| (key.matches('*').count(), *key))?;
+        let vars = event.vars();
+        let subst = |s: &String| {
+            vars.iter().fold(s.clone(), |s, (var, val)| {
+                s.replace(&format!("${{{var}}}"), val)
+            })
+        };
+        Some(Trigger {
+            ignore_errors: trigger.ignore_errors,
+            cmds: trigger
+                .cmds
+                .iter()
+                .map(|pkg| Package {
+                    cmd: subst(&pkg.cmd),
+                    args: pkg.args.iter().map(subst).collect(),
+                    tag: None,
+                })
+                .collect(),
+        })
+    }
+}
 
 pub fn load_bots() -> Vec<Bot> {
     let mut bots: Vec<Bot> = vec![serde_json::from_str(DEFAULT_BOT_JSON).unwrap()];
@@ -31,23 +125,6 @@ pub fn load_bots() -> Vec<Bot> {
     bots
 }
 
-pub fn bot_thread(trigger: Receiver<String>) -> BotList {
-    let mut map = HashMap::new();
-    for bot in load_bots() {
-        map.insert(bot.name.clone(), bot);
-    }
-    let ret = Arc::new(Mutex::new(map));
-    let bots = ret.clone();
-    thread::spawn(move || {
-        while let Ok(trig) = trigger.recv() {
-            for bot in bots.lock().unwrap().values() {
-
-            }
-        }
-    });
-    ret
-}
-
 #[cfg(test)]
 mod test {
     use super::*;
@@ -56,4 +133,46 @@ mod test {
     fn default_parses() {
         let _: Bot = serde_json::from_str(DEFAULT_BOT_JSON).unwrap();
     }
+
+    #[test]
+    fn trigger_matching() {
+        let bot: Bot = serde_json::from_str(DEFAULT_BOT_JSON).unwrap();
+        assert!(bot.triggered(&Event::Startup).is_some());
+        assert!(bot.triggered(&Event::Subscribe("other", "me")).is_none());
+        let trig = bot.triggered(&Event::Subscribe("printf", "me")).unwrap();
+        assert_eq!(trig.cmds[0].args, ["printf", "A wild me has appeared!"]);
+        assert!(Event::Subscribe("any", "me").matches("subscribe(*)"));
+        assert!(!Event::Login("me").matches("login(me)"));
+    }
+
+    #[test]
+    fn most_specific_trigger() {
+        let trigger = |msg: &str| Trigger {
+            ignore_errors: false,
+            cmds: vec![Package {
+                cmd: msg.to_string(),
+                ..Default::default()
+            }],
+        };
+        // every map gets its own hash seed, and with it its own order
+        for _ in 0..10 {
+            let bot = Bot {
+                name: "bot".to_string(),
+                key: String::new(),
+                trigger: HashMap::from([
+                    ("subscribe(*)".to_string(), trigger("any")),
+                    ("subscribe(printf)".to_string(), trigger("printf")),
+                ]),
+            };
+            let fired = |channel| {
+                bot.triggered(&Event::Subscribe(channel, "me"))
+                    .unwrap()
+                    .cmds[0]
+                    .cmd
+                    .clone()
+            };
+            assert_eq!(fired("printf"), "printf");
+            assert_eq!(fired("rust"), "any");
+        }
+    }
 }
@@ -0,0 +1,143 @@
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind},
+    net::SocketAddr,
+    sync::{atomic::AtomicBool, mpsc, Arc, Mutex},
+};
+
+use mio::{Poll, Waker};
+
+use super::{
+    accounts::Accounts,
+    bot::{self, Event},
+    channel::Channel,
+    history::History,
+    login::{self, Hasher},
+    pardons::Pardons,
+    sanctions::Sanctions,
+    Metrics, Server, ServerConfig, WAKER_TOKEN,
+};
+
+/// Sets up a [`Server`], e.g. to embed it into another program
+///
+/// Without further settings, this is the same as
+/// [`Server::new`] with the default [`ServerConfig`].
+#[derive(Default)]
+pub struct ServerBuilder {
+    config: ServerConfig,
+    addrs: Vec<SocketAddr>,
+    no_listeners: bool,
+}
+
+impl ServerBuilder {
+    /// use these settings instead of the defaults
+    pub fn config(mut self, config: ServerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// listen on `addr` instead of the configured addresses
+    ///
+    /// May be called multiple times to listen on several addresses.
+    /// With port 0, the OS picks a free port, see [`Server::addrs`].
+    pub fn bind(mut self, addr: impl Into<SocketAddr>) -> Self {
+        self.addrs.push(addr.into());
+        self
+    }
+
+    /// don't listen on any address
+    ///
+    /// Clients can then only connect through [`Server::accept`],
+    /// e.g. to test a server without opening any sockets.
+    pub fn without_listeners(mut self) -> Self {
+        self.no_listeners = true;
+        self
+    }
+
+    /// bind the listeners, load accounts, histories and bots, and start the bots
+    ///
+    /// Fails with [`ErrorKind::InvalidInput`] if the config
+    /// doesn't pass [`ServerConfig::validate`].
+    pub fn build(self) -> Result<Server, Error> {
+        let config = self.config;
+        config
+            .validate()
+            .map_err(|why| Error::new(ErrorKind::InvalidInput, why.to_string()))?;
+        let addrs = if self.addrs.is_empty() {
+            config.addrs()
+        } else {
+            self.addrs
+        };
+        let (tx, rx) = mpsc::channel();
+        let poll = Poll::new()?;
+        let waker = Arc::new(Waker::new(poll.registry(), WAKER_TOKEN)?);
+        let running = Arc::new(AtomicBool::new(true));
+        let tls = match &config.tls {
+            Some(tls) => Some(crate::tls::server_config(&tls.cert, &tls.key)?),
+            None => None,
+        };
+        let sanctions = Arc::new(Mutex::new(Sanctions {
+            admins: config.admins.clone(),
+            ..Default::default()
+        }));
+        let mut wakers = vec![waker.clone()];
+        let hasher = Hasher::start(tx.clone(), waker.clone());
+        let (addrs, login_thread) = if self.no_listeners {
+            (Vec::new(), None)
+        } else {
+            let (addrs, login_waker, login_thread) = login::login_thread(
+                tx,
+                waker,
+                running.clone(),
+                sanctions.clone(),
+                &addrs,
+                config.limits(),
+                tls,
+            )?;
+            wakers.push(Arc::new(login_waker));
+            (addrs, Some(login_thread))
+        };
+        let accounts = Accounts::load(config.accounts.clone())?;
+        let bots: HashMap<_, _> = bot::load_bots()
+            .into_iter()
+            .map(|bot| (bot.name.clone(), bot))
+            .collect();
+        let global = Channel {
+            members: bots.keys().cloned().collect(),
+            history: History::load(&config),
+            ..Default::default()
+        };
+        // continue after stored messages, so ids stay unique
+        let last_msg_id = global
+            .history
+            .last_id()
+            .max(accounts.last_msg_id())
+            .unwrap_or_default();
+        let mut server = Server {
+            config,
+            addrs,
+            poll,
+            wakers,
+            running,
+            next_token: WAKER_TOKEN.0 + 1,
+            login_thread,
+            login_rx: rx,
+            hasher: Some(hasher),
+            login_buckets: HashMap::new(),
+            console_rx: None,
+            pending: Vec::new(),
+            active_clients: HashMap::new(),
+            passive_clients: Vec::new(),
+            channels: HashMap::from([(String::new(), global)]),
+            bots,
+            accounts,
+            sanctions,
+            pardons: Pardons::default(),
+            last_msg_id,
+            metrics: Metrics::default(),
+        };
+        // here instead of in `run`, so servers driven by `step` start their bots as well
+        server.trigger_bots(Event::Startup);
+        Ok(server)
+    }
+}
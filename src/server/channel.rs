@@ -0,0 +1,115 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use super::history::{History, Message};
+use crate::{package::Package, response::Response};
+
+/// Privileges of a channel member
+///
+/// Members may only moderate members with a lower role.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+pub enum Role {
+    Member,
+    Operator,
+    Founder,
+}
+
+#[derive(Default)]
+pub struct Channel {
+    pub name: String,
+    pub password: String,
+    pub topic: String,
+    /// the client that created the channel
+    ///
+    /// empty for the global channel, which has no founder
+    pub founder: String,
+    pub members: HashSet<String>,
+    /// members with moderation rights, always a subset of `members`
+    pub operators: HashSet<String>,
+    /// names that may not subscribe to the channel
+    pub banned: HashSet<String>,
+    /// minimum time between two posts of a member, zero if off
+    ///
+    /// Operators are exempt.
+    pub slow_mode: Duration,
+    /// when members last posted, only tracked in slow mode
+    pub last_post: HashMap<String, Instant>,
+    pub msg_queue: Vec<Package>,
+    pub history: History,
+}
+
+impl Channel {
+    pub fn new(name: String, password: String, founder: String, history: History) -> Self {
+        Self {
+            name,
+            password,
+            members: HashSet::from([founder.clone()]),
+            founder,
+            history,
+            ..Default::default()
+        }
+    }
+
+    pub fn append_msg(&mut self, msg: Message) {
+        self.msg_queue.push(msg.package(&self.name));
+        self.history.push(msg);
+    }
+
+    /// the role of a client, or `None` if it isn't a member
+    pub fn role(&self, name: &str) -> Option<Role> {
+        if !self.members.contains(name) {
+            None
+        } else if name == self.founder {
+            Some(Role::Founder)
+        } else if self.operators.contains(name) {
+            Some(Role::Operator)
+        } else {
+            Some(Role::Member)
+        }
+    }
+
+    /// tell all members that `name` joined or left
+    ///
+    /// see [`Response::Member`] for possible events
+    pub fn notify(&mut self, name: &str, event: &str) {
+        self.msg_queue
+            .push(Response::member(&self.name, name, event).package());
+    }
+
+    pub fn remove_member(&mut self, name: &str) {
+        self.members.remove(name);
+        self.operators.remove(name);
+        self.last_post.remove(name);
+    }
+
+    /// forget a founder without an account once they left
+    ///
+    /// Otherwise, anyone logging in as a guest with
+    /// their name later on would take over the channel.
+    pub fn check_founder(&mut self, registered: bool) {
+        if !registered && !self.members.contains(&self.founder) {
+            self.founder.clear();
+        }
+    }
+
+    /// record a post by `name`, unless slow mode makes it wait
+    pub fn check_slow_mode(&mut self, name: &str) -> Result<(), Response> {
+        if self.slow_mode.is_zero() || self.role(name) >= Some(Role::Operator) {
+            return Ok(());
+        }
+        let now = Instant::now();
+        if let Some(last) = self.last_post.get(name) {
+            let wait = self.slow_mode.saturating_sub(now - *last);
+            if !wait.is_zero() {
+                return Err(Response::err(format!(
+                    "slow mode, wait {:.0} seconds",
+                    wait.as_secs_f64().ceil()
+                )));
+            }
+        }
+        self.last_post.insert(name.to_string(), now);
+        Ok(())
+    }
+}
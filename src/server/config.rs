@@ -0,0 +1,277 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    io::Error,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use super::rate::RateLimit;
+use crate::{connection::Limits, SERVER_PORT};
+
+/// Settings for a [`Server`](super::Server)
+///
+/// Every field has a default, so a config file
+/// only needs to contain the settings to change, e.g.
+/// ```toml
+/// bind = ["0.0.0.0"]
+/// port = 7000
+/// ipv6 = true
+/// guests = false
+/// admins = ["alice"]
+/// max_offenses = 3
+/// offense_ban_secs = 3600
+///
+/// [rate_limits]
+/// post = { per_second = 1, burst = 10 }
+/// names = { per_second = 0.2, burst = 3 }
+///
+/// [tls]
+/// cert = "cert.pem"
+/// key = "key.pem"
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// addresses of the interfaces to listen on
+    pub bind: Vec<IpAddr>,
+    /// port to listen on
+    pub port: u16,
+    /// additionally listen on the IPv6 counterparts of
+    /// the unspecified and loopback addresses in `bind`
+    pub ipv6: bool,
+    /// allow logging in without an account
+    pub guests: bool,
+    /// names of the accounts allowed to kick, ban and mute users
+    pub admins: Vec<String>,
+    /// offenses after which a client is kicked
+    pub max_offenses: u8,
+    /// seconds after which an offense is forgotten, 0 to never forget it
+    pub offense_expiry_secs: u64,
+    /// seconds a client kicked for its offenses is banned, by name and address
+    ///
+    /// With 0, it may log in again right away.
+    pub offense_ban_secs: u64,
+    /// number of pardons a client may grant within `pardon_window_secs`
+    ///
+    /// Guests share the limit with everyone connecting from the same address,
+    /// registered users have their own. Admins may pardon as often as they like.
+    pub max_pardons: usize,
+    /// seconds a pardon counts towards the limit of its pardoner
+    pub pardon_window_secs: u64,
+    /// failed logins in a row after which an account, or the address
+    /// they came from, can't be logged into for `failed_login_lock_secs`
+    pub max_failed_logins: usize,
+    /// seconds logins stay locked after too many failures
+    pub failed_login_lock_secs: u64,
+    /// file to store registered accounts in
+    ///
+    /// If not set, accounts are lost when the server stops.
+    pub accounts: Option<PathBuf>,
+    /// number of messages to remember per channel
+    pub history_len: usize,
+    /// directory to store the history of the global channel in
+    ///
+    /// If not set, it is only kept in memory, like those of all other channels.
+    pub history_dir: Option<PathBuf>,
+    /// number of direct messages stored for a registered user while offline
+    pub mailbox_len: usize,
+    /// bytes that may wait to be sent to a client
+    ///
+    /// Clients that don't read fast enough to stay below are disconnected.
+    pub max_queued_bytes: usize,
+    /// maximum size of a package sent by a client in bytes
+    pub max_package_len: usize,
+    /// maximum number of arguments of a package sent by a client
+    pub max_args: usize,
+    /// maximum length of messages and topics in characters
+    pub max_msg_len: usize,
+    /// how often clients may make each kind of request, by command, e.g. `post`
+    ///
+    /// Requests without an entry are not limited. Limits for `auth` and `register`
+    /// also apply to clients that are logging in, per address instead of per client.
+    /// Setting this replaces the defaults for `post`, `send`, `auth` and `register`.
+    pub rate_limits: HashMap<String, RateLimit>,
+    /// only accept TLS connections, using this certificate
+    pub tls: Option<TlsConfig>,
+}
+
+/// Certificate of a [`Server`](super::Server) accepting TLS connections
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    /// PEM file with the certificate chain, starting with the server's own
+    pub cert: PathBuf,
+    /// PEM file with the private key of the certificate
+    pub key: PathBuf,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+            port: SERVER_PORT,
+            ipv6: false,
+            guests: true,
+            admins: Vec::new(),
+            max_offenses: 5,
+            offense_expiry_secs: 600,
+            offense_ban_secs: 300,
+            max_pardons: 3,
+            pardon_window_secs: 3600,
+            max_failed_logins: 5,
+            failed_login_lock_secs: 300,
+            accounts: Some(PathBuf::from("accounts.json")),
+            history_len: 100,
+            history_dir: None,
+            mailbox_len: 50,
+            max_queued_bytes: 1 << 20,
+            max_package_len: 16 << 10,
+            max_args: 16,
+            max_msg_len: 2000,
+            rate_limits: [
+                ("post", 1.0),
+                ("send", 1.0),
+                ("auth", 0.2),
+                ("register", 0.2),
+            ]
+            .map(|(cmd, per_second)| {
+                let limit = RateLimit {
+                    per_second,
+                    burst: 10,
+                };
+                (cmd.to_string(), limit)
+            })
+            .into(),
+            tls: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// read the config from a TOML file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigErr> {
+        let config: Self = toml::from_str(&std::fs::read_to_string(path)?)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// check for settings that would keep the server from working
+    pub fn validate(&self) -> Result<(), ConfigErr> {
+        if self.max_offenses == 0 {
+            // every client would be kicked and banned right away
+            return Err(ConfigErr::Invalid("max_offenses must be at least 1".into()));
+        }
+        for (cmd, limit) in &self.rate_limits {
+            if !(limit.per_second.is_finite() && limit.per_second >= 0.0) {
+                return Err(ConfigErr::Invalid(format!(
+                    "per_second of the rate limit for {cmd} must be a number of at least 0"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// bounds for packages sent by clients
+    pub fn limits(&self) -> Limits {
+        Limits {
+            max_len: self.max_package_len,
+            max_args: self.max_args,
+        }
+    }
+
+    /// all socket addresses the server should listen on
+    pub fn addrs(&self) -> Vec<SocketAddr> {
+        let mut ips = self.bind.clone();
+        if self.ipv6 {
+            for ip in &self.bind {
+                let v6 = match ip {
+                    IpAddr::V4(v4) if v4.is_unspecified() => Ipv6Addr::UNSPECIFIED,
+                    IpAddr::V4(v4) if v4.is_loopback() => Ipv6Addr::LOCALHOST,
+                    _ => continue,
+                };
+                ips.push(IpAddr::V6(v6));
+            }
+        }
+        let mut seen = HashSet::new();
+        ips.retain(|ip| seen.insert(*ip));
+        ips.into_iter()
+            .map(|ip| SocketAddr::new(ip, self.port))
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigErr {
+    IoError(Error),
+    ParseErr(toml::de::Error),
+    Invalid(String),
+}
+
+impl Display for ConfigErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigErr::IoError(err) => write!(f, "failed to read config: {err}"),
+            ConfigErr::ParseErr(err) => write!(f, "invalid config: {err}"),
+            ConfigErr::Invalid(why) => write!(f, "invalid config: {why}"),
+        }
+    }
+}
+
+impl From<Error> for ConfigErr {
+    fn from(value: Error) -> Self {
+        Self::IoError(value)
+    }
+}
+
+impl From<toml::de::Error> for ConfigErr {
+    fn from(value: toml::de::Error) -> Self {
+        Self::ParseErr(value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let config: ServerConfig = toml::from_str("bind = [\"0.0.0.0\"]\nipv6 = true").unwrap();
+        assert_eq!(
+            config.addrs(),
+            [
+                SocketAddr::from(([0, 0, 0, 0], SERVER_PORT)),
+                SocketAddr::from((Ipv6Addr::UNSPECIFIED, SERVER_PORT)),
+            ]
+        );
+        let config: ServerConfig =
+            toml::from_str("bind = [\"::\", \"0.0.0.0\"]\nipv6 = true").unwrap();
+        assert_eq!(
+            config.addrs(),
+            [
+                SocketAddr::from((Ipv6Addr::UNSPECIFIED, SERVER_PORT)),
+                SocketAddr::from(([0, 0, 0, 0], SERVER_PORT)),
+            ]
+        );
+        assert!(toml::from_str::<ServerConfig>("unknown = 1").is_err());
+        let config: ServerConfig =
+            toml::from_str("[rate_limits]\nping = { per_second = 2, burst = 5 }").unwrap();
+        assert_eq!(config.rate_limits["ping"].per_second, 2.0);
+        assert!(!config.rate_limits.contains_key("post"));
+        assert!(config.validate().is_ok());
+        for invalid in [
+            "max_offenses = 0",
+            "[rate_limits]\nping = { per_second = -1, burst = 5 }",
+            "[rate_limits]\nping = { per_second = nan, burst = 5 }",
+        ] {
+            let config: ServerConfig = toml::from_str(invalid).unwrap();
+            assert!(config.validate().is_err(), "{invalid}");
+        }
+        let config: ServerConfig =
+            toml::from_str("[tls]\ncert = \"cert.pem\"\nkey = \"key.pem\"").unwrap();
+        assert_eq!(config.tls.unwrap().key, PathBuf::from("key.pem"));
+        assert!(toml::from_str::<ServerConfig>("[tls]\ncert = \"cert.pem\"").is_err());
+    }
+}
@@ -0,0 +1,126 @@
+use std::{
+    io::stdin,
+    sync::{mpsc::Sender, Arc},
+    thread,
+};
+
+use mio::Waker;
+
+use super::{ConfigErr, ServerConfig};
+
+pub const HELP: &str = "operator commands:
+ clients          list connected clients and their offenses
+ metrics          show how much data is waiting to be sent
+ say <notice>     send a notice to every client
+ kick <name>      disconnect a client
+ close <channel>  remove a channel and all its members
+ reload           read the config again
+ stop             disconnect everyone and stop the server
+ help             print this help";
+
+/// Things the person running the server wants to do
+pub enum Command {
+    Clients,
+    Metrics,
+    Broadcast(String),
+    Kick(String),
+    Close(String),
+    /// apply the config that was read again
+    Reload(Box<ServerConfig>),
+    Stop,
+    Help,
+}
+
+impl Command {
+    /// parse a line typed by the operator, except for `reload`
+    ///
+    /// Returns `None` for empty lines.
+    fn parse(line: &str) -> Result<Option<Self>, String> {
+        let (cmd, arg) = line
+            .trim()
+            .split_once(char::is_whitespace)
+            .map_or((line.trim(), ""), |(cmd, arg)| (cmd, arg.trim()));
+        let required = |what| {
+            if arg.is_empty() {
+                Err(format!("please provide {what}"))
+            } else {
+                Ok(arg.to_string())
+            }
+        };
+        Ok(Some(match cmd {
+            "" => return Ok(None),
+            "clients" => Self::Clients,
+            "metrics" => Self::Metrics,
+            "say" => Self::Broadcast(required("a notice")?),
+            "kick" => Self::Kick(required("a name")?),
+            "close" => Self::Close(required("a channel")?),
+            "stop" => Self::Stop,
+            "help" | "?" => Self::Help,
+            _ => return Err(format!("unknown command {cmd}, try 'help'")),
+        }))
+    }
+}
+
+/// read operator commands from stdin
+///
+/// Commands are sent through `tx`, followed by waking up `waker`.
+/// For `reload`, the config is read by calling `reload` on this thread,
+/// so the server doesn't wait for the disk.
+/// The thread ends with stdin or the server.
+pub fn console_thread(
+    tx: Sender<Command>,
+    waker: Arc<Waker>,
+    reload: impl Fn() -> Result<ServerConfig, ConfigErr> + Send + 'static,
+) {
+    thread::spawn(move || {
+        for line in stdin().lines() {
+            let Ok(line) = line else {
+                return;
+            };
+            let cmd = if line.trim() == "reload" {
+                reload()
+                    .map(|config| Some(Command::Reload(Box::new(config))))
+                    .map_err(|why| why.to_string())
+            } else {
+                Command::parse(&line)
+            };
+            match cmd {
+                Ok(Some(cmd)) => {
+                    if tx.send(cmd).is_err() || waker.wake().is_err() {
+                        return;
+                    }
+                }
+                Ok(None) => {}
+                Err(why) => println!("{why}"),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert!(matches!(Command::parse("  "), Ok(None)));
+        assert!(matches!(
+            Command::parse("clients"),
+            Ok(Some(Command::Clients))
+        ));
+        assert!(matches!(
+            Command::parse("say  server restarts soon "),
+            Ok(Some(Command::Broadcast(notice))) if notice == "server restarts soon"
+        ));
+        assert!(matches!(
+            Command::parse(" kick bob"),
+            Ok(Some(Command::Kick(name))) if name == "bob"
+        ));
+        assert!(matches!(
+            Command::parse("metrics"),
+            Ok(Some(Command::Metrics))
+        ));
+        assert!(Command::parse("close").is_err());
+        assert!(Command::parse("shout").is_err());
+    }
+}
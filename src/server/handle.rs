@@ -0,0 +1,33 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use mio::Waker;
+
+/// Controls a [`Server`](super::Server) from another thread
+#[derive(Clone)]
+pub struct ServerHandle {
+    pub(super) running: Arc<AtomicBool>,
+    /// wake up the server and its login thread, if any
+    pub(super) wakers: Vec<Arc<Waker>>,
+}
+
+impl ServerHandle {
+    /// make [`Server::run`](super::Server::run) disconnect all clients and return
+    ///
+    /// New clients aren't accepted anymore. When driving the server through
+    /// [`Server::step`](super::Server::step), connected clients stay connected
+    /// until [`Server::shutdown`](super::Server::shutdown) is called or the server is dropped.
+    pub fn stop(&self) {
+        self.running.store(false, Ordering::Relaxed);
+        for waker in &self.wakers {
+            // a waker only fails if the thread it wakes is gone
+            let _ = waker.wake();
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+}
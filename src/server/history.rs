@@ -0,0 +1,196 @@
+use std::{
+    collections::VecDeque,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::ServerConfig;
+use crate::{package::Package, response::Response};
+
+/// A message posted to a channel or sent directly to a user
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Message {
+    /// server-assigned, increasing with every message
+    #[serde(default)]
+    pub id: u64,
+    /// seconds since the unix epoch (UTC)
+    #[serde(default)]
+    pub time: u64,
+    pub name: String,
+    pub msg: String,
+}
+
+impl Message {
+    /// create a message sent now
+    pub fn new(id: u64, name: String, msg: String) -> Self {
+        let time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        Self {
+            id,
+            time,
+            name,
+            msg,
+        }
+    }
+
+    /// the `Msg` response delivering this message
+    pub fn package(&self, channel: &str) -> Package {
+        Response::msg(
+            channel,
+            &self.name,
+            &self.msg,
+            self.id.to_string(),
+            self.time.to_string(),
+        )
+        .package()
+    }
+
+    /// the arguments representing this message in an `Info` response
+    pub fn info_args(&self) -> [String; 4] {
+        [
+            self.id.to_string(),
+            self.time.to_string(),
+            self.name.clone(),
+            self.msg.clone(),
+        ]
+    }
+}
+
+/// The most recent messages of a channel
+///
+/// If a history directory is configured, messages of the global channel are
+/// also appended to a file with one JSON object per line. Other channels don't
+/// outlive the server, so their histories are only kept in memory.
+#[derive(Default)]
+pub struct History {
+    max_len: usize,
+    msgs: VecDeque<Message>,
+    file: Option<PathBuf>,
+}
+
+impl History {
+    /// start an empty history of a new channel, only kept in memory
+    pub fn new(config: &ServerConfig) -> Self {
+        Self {
+            max_len: config.history_len,
+            msgs: VecDeque::new(),
+            file: None,
+        }
+    }
+
+    /// restore the stored history of the global channel
+    pub fn load(config: &ServerConfig) -> Self {
+        let mut history = Self {
+            max_len: config.history_len,
+            msgs: VecDeque::new(),
+            file: config
+                .history_dir
+                .as_ref()
+                .map(|dir| dir.join("global.jsonl")),
+        };
+        let Some(file) = &history.file else {
+            return history;
+        };
+        let Ok(content) = fs::read_to_string(file) else {
+            return history;
+        };
+        for line in content.lines() {
+            match serde_json::from_str(line) {
+                Ok(msg) => history.remember(msg),
+                Err(why) => eprintln!("skipping invalid history entry: {why}"),
+            }
+        }
+        // drop everything that didn't fit
+        history.remove_file();
+        for msg in &history.msgs {
+            history.append_to_file(msg);
+        }
+        history
+    }
+
+    pub fn push(&mut self, msg: Message) {
+        self.append_to_file(&msg);
+        self.remember(msg);
+    }
+
+    /// id of the most recent message
+    pub fn last_id(&self) -> Option<u64> {
+        self.msgs.back().map(|m| m.id)
+    }
+
+    /// get up to `count` of the most recent messages, oldest first
+    pub fn recent(&self, count: usize) -> impl Iterator<Item = &Message> {
+        self.msgs.iter().skip(self.msgs.len().saturating_sub(count))
+    }
+
+    fn remove_file(&self) {
+        if let Some(file) = &self.file {
+            if file.exists() {
+                if let Err(why) = fs::remove_file(file) {
+                    eprintln!("failed to remove history: {why}");
+                }
+            }
+        }
+    }
+
+    fn remember(&mut self, msg: Message) {
+        self.msgs.push_back(msg);
+        while self.msgs.len() > self.max_len {
+            self.msgs.pop_front();
+        }
+    }
+
+    fn append_to_file(&self, msg: &Message) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        let line = serde_json::to_string(msg).expect("messages should serialize");
+        let res = fs::create_dir_all(file.parent().expect("file should be in history dir"))
+            .and_then(|_| OpenOptions::new().create(true).append(true).open(file))
+            .and_then(|mut f| writeln!(f, "{line}"));
+        if let Err(why) = res {
+            eprintln!("failed to store history: {why}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bounded() {
+        let config = ServerConfig {
+            history_len: 3,
+            ..Default::default()
+        };
+        let mut history = History::new(&config);
+        for i in 0..5 {
+            history.push(Message::new(i, "me".to_string(), i.to_string()));
+        }
+        let msgs: Vec<_> = history.recent(10).map(|m| m.msg.as_str()).collect();
+        assert_eq!(msgs, ["2", "3", "4"]);
+        let msgs: Vec<_> = history.recent(1).map(|m| m.msg.as_str()).collect();
+        assert_eq!(msgs, ["4"]);
+    }
+
+    #[test]
+    fn only_global_is_stored() {
+        let dir = std::env::temp_dir().join(format!("rs_chat_history_{}", std::process::id()));
+        let config = ServerConfig {
+            history_dir: Some(dir.clone()),
+            ..Default::default()
+        };
+        let mut global = History::load(&config);
+        global.push(Message::new(1, "me".to_string(), "hi".to_string()));
+        History::new(&config).push(Message::new(2, "me".to_string(), "ho".to_string()));
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        assert_eq!(History::load(&config).last_id(), Some(1));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,51 +1,278 @@
-use std::{io::Error, net::TcpListener, sync::mpsc::Sender, thread, time::Duration};
+use std::{
+    collections::HashMap,
+    io::Error,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Sender, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
 
-use crate::{connection::Connection, requests::Request, response::Response, SERVER_PORT};
+use mio::{net::TcpListener, Events, Interest, Poll, Token, Waker};
+use socket2::{Domain, Protocol, Socket, Type};
 
-use super::Client;
+use super::{accounts, sanctions::Sanctions, Server};
+use crate::{
+    connection::{self, poll_events, Connection, Limits},
+    requests::Request,
+    response::Response,
+    transport::Transport,
+};
 
-const MAX_AGE: u32 = 200;
+/// How a new connection wants to log in
+pub enum LoginMethod {
+    /// passive client, see [`Request::Listen`]
+    Listen,
+    /// name-only login, see [`Request::Login`]
+    Guest(String),
+    /// name and password, see [`Request::Authenticate`]
+    Password(String, String),
+    /// name and password for a new account, see [`Request::Register`]
+    Register(String, String),
+    /// name and whether the password was right, see [`Hasher`]
+    Verified(String, bool),
+    /// name and password hash for a new account, see [`Hasher`]
+    Hashed(String, String),
+}
 
-pub fn login_thread(tx: Sender<Client>) -> Result<(), Error> {
-    let listener = TcpListener::bind(("127.0.0.1", SERVER_PORT))?;
-    listener.set_nonblocking(true)?;
-    thread::spawn(move || {
-        let mut incoming: Vec<(Connection, u32)> = Vec::new();
-        loop {
-            while let Ok((stream, _)) = listener.accept() {
-                if let Ok(conn) = Connection::new(stream) {
-                    incoming.push((conn, 0));
+impl LoginMethod {
+    /// the name to log in with, `None` for passive clients
+    pub fn name(&self) -> Option<&String> {
+        match self {
+            LoginMethod::Listen => None,
+            LoginMethod::Guest(name)
+            | LoginMethod::Password(name, _)
+            | LoginMethod::Register(name, _)
+            | LoginMethod::Verified(name, _)
+            | LoginMethod::Hashed(name, _) => Some(name),
+        }
+    }
+}
+
+/// A password to check or hash, along with the connection logging in with it
+pub struct HashJob {
+    pub conn: Connection,
+    pub addr: Option<IpAddr>,
+    pub name: String,
+    pub password: String,
+    /// stored hash to check the password against, `None` to hash it for a new account
+    pub hash: Option<String>,
+}
+
+/// jobs that may wait for the [`Hasher`] before new ones are turned away
+pub const HASH_QUEUE_LEN: usize = 32;
+
+/// Checks and hashes passwords off the event loop, as argon2 is slow on purpose
+pub struct Hasher {
+    jobs: SyncSender<HashJob>,
+    thread: JoinHandle<()>,
+}
+
+impl Hasher {
+    /// start the thread doing the hashing
+    ///
+    /// Results are sent through `tx` as [`LoginMethod::Verified`]
+    /// or [`LoginMethod::Hashed`], followed by waking up `waker`.
+    pub fn start(tx: Sender<(Connection, LoginMethod, Option<IpAddr>)>, waker: Arc<Waker>) -> Self {
+        let (jobs, rx) = mpsc::sync_channel::<HashJob>(HASH_QUEUE_LEN);
+        let thread = thread::spawn(move || {
+            for job in rx {
+                let method = match job.hash {
+                    Some(hash) => LoginMethod::Verified(
+                        job.name,
+                        accounts::verify_password(&hash, &job.password),
+                    ),
+                    None => LoginMethod::Hashed(job.name, accounts::hash_password(&job.password)),
+                };
+                if tx.send((job.conn, method, job.addr)).is_err() {
+                    // the server is gone
+                    return;
                 }
+                waker.wake().expect("failed to wake up server");
             }
-            incoming = incoming
-                .into_iter()
-                .flat_map(|(mut conn, age)| match try_login(&mut conn) {
-                    Ok(name) => {
-                        tx.send(Client::new(conn, name)).expect("server died");
-                        None
-                    }
-                    Err(()) => (age < MAX_AGE).then_some((conn, age + 1)),
+        });
+        Self { jobs, thread }
+    }
+
+    /// queue a job, or turn the client away if too many are waiting already
+    pub fn send(&self, job: HashJob) {
+        if let Err(TrySendError::Full(mut job) | TrySendError::Disconnected(mut job)) =
+            self.jobs.try_send(job)
+        {
+            let busy = Response::err("server busy, try again later");
+            job.conn.send_package(busy.package());
+        }
+    }
+
+    /// wait until the results of all jobs sent so far have been sent
+    pub fn finish(self) {
+        drop(self.jobs);
+        let _ = self.thread.join();
+    }
+}
+
+/// the login thread, returning the connections that didn't log in yet
+pub type LoginThread = JoinHandle<Vec<Connection>>;
+
+/// time a new connection has to log in
+pub const LOGIN_TIMEOUT: Duration = Duration::from_secs(10);
+/// listeners are registered with their index as token, so use the other end
+const STOP_TOKEN: Token = Token(usize::MAX);
+
+/// start accepting new clients on all given addresses
+///
+/// Logged in connections are sent through `tx` along with the
+/// address of the client, followed by waking up `waker`. Incoming packages are bounded by `limits`.
+/// If `tls` is given, all connections must be encrypted.
+/// Clients banned according to `sanctions` are turned away after their login request.
+///
+/// The thread closes the listeners and ends once `running` is cleared
+/// and it is woken up through the returned waker, or the server is gone.
+/// It then hands back the connections that haven't logged in yet, so they
+/// can be told about the shutdown. Returns the addresses actually bound,
+/// along with that waker and the thread.
+pub fn login_thread(
+    tx: Sender<(Connection, LoginMethod, Option<IpAddr>)>,
+    waker: Arc<Waker>,
+    running: Arc<AtomicBool>,
+    sanctions: Arc<Mutex<Sanctions>>,
+    addrs: &[SocketAddr],
+    limits: Limits,
+    tls: Option<Arc<rustls::ServerConfig>>,
+) -> Result<(Vec<SocketAddr>, Waker, LoginThread), Error> {
+    let mut listeners = addrs
+        .iter()
+        .map(|addr| bind(*addr))
+        .collect::<Result<Vec<_>, _>>()?;
+    let bound = listeners
+        .iter()
+        .map(TcpListener::local_addr)
+        .collect::<Result<_, _>>()?;
+    let mut poll = Poll::new()?;
+    for (idx, listener) in listeners.iter_mut().enumerate() {
+        poll.registry()
+            .register(listener, Token(idx), Interest::READABLE)?;
+    }
+    let stop = Waker::new(poll.registry(), STOP_TOKEN)?;
+    let thread = thread::spawn(move || {
+        let mut events = Events::with_capacity(128);
+        let mut incoming: HashMap<Token, (Connection, IpAddr, Instant)> = HashMap::new();
+        let mut next_token = listeners.len();
+        loop {
+            let timeout = incoming
+                .values()
+                .map(|(_, _, since)| {
+                    (*since + LOGIN_TIMEOUT).saturating_duration_since(Instant::now())
                 })
-                .collect();
-            thread::sleep(Duration::from_millis(50));
+                .min();
+            if let Err(why) = poll_events(&mut poll, &mut events, timeout) {
+                eprintln!("failed to wait for new clients: {why}");
+                break;
+            }
+            if !running.load(Ordering::Relaxed) {
+                break;
+            }
+            for event in &events {
+                if let Some(listener) = listeners.get(event.token().0) {
+                    while let Ok((stream, addr)) = listener.accept() {
+                        let stream: Box<dyn Transport> = Box::new(stream);
+                        let conn = match &tls {
+                            Some(config) => Connection::new_tls(stream, config.clone()),
+                            None => Ok(Connection::new(stream)),
+                        };
+                        let Ok(mut conn) = conn else {
+                            continue;
+                        };
+                        conn.set_limits(limits);
+                        let token = Token(next_token);
+                        next_token += 1;
+                        if conn.register(poll.registry(), token).is_ok() {
+                            incoming.insert(token, (conn, addr.ip(), Instant::now()));
+                        }
+                    }
+                    continue;
+                }
+                let Some((conn, ..)) = incoming.get_mut(&event.token()) else {
+                    continue;
+                };
+                if let Ok(method) = try_login(conn) {
+                    let (mut conn, addr, _) = incoming
+                        .remove(&event.token())
+                        .expect("connection was just used");
+                    let banned = sanctions
+                        .lock()
+                        .expect("server panicked")
+                        .turns_away(method.name(), addr);
+                    if banned {
+                        conn.close_with(Response::err("banned from server").package());
+                        continue;
+                    }
+                    if conn.deregister(poll.registry()).is_ok() {
+                        if tx.send((conn, method, Some(addr))).is_err() {
+                            // the server was dropped without being stopped
+                            return Vec::new();
+                        }
+                        waker.wake().expect("failed to wake up server");
+                    }
+                }
+            }
+            incoming.retain(|_, (conn, _, since)| conn.alive() && since.elapsed() < LOGIN_TIMEOUT);
         }
+        incoming
+            .into_values()
+            .filter_map(|(mut conn, ..)| conn.deregister(poll.registry()).ok().map(|_| conn))
+            .collect()
     });
-    Ok(())
+    Ok((bound, stop, thread))
 }
 
-fn try_login(conn: &mut Connection) -> Result<Option<String>, ()> {
-    if let Some(pkg) = conn.get_package() {
-        match pkg.try_into() {
-            Ok(Request::Login(name)) => {
-                if name.is_empty() {
-                    conn.send_package(Response::err("please provide a name").package());
-                } else {
-                    return Ok(Some(name));
-                }
+fn bind(addr: SocketAddr) -> Result<TcpListener, Error> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        // otherwise, `::` would also claim the port for IPv4
+        socket.set_only_v6(true)?;
+    }
+    // same as `TcpListener::bind`, allows restarting while old connections linger
+    #[cfg(not(windows))]
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(128)?;
+    socket.set_nonblocking(true)?;
+    Ok(TcpListener::from_std(socket.into()))
+}
+
+/// handle all packages sent by a connection that isn't logged in yet
+pub fn try_login(conn: &mut Connection) -> Result<LoginMethod, ()> {
+    while let Some(pkg) = conn.get_package() {
+        match Request::parse(pkg) {
+            Ok(
+                Request::Login(name) | Request::Authenticate(name, _) | Request::Register(name, _),
+            ) if name.is_empty() => {
+                conn.send_package(Response::err("please provide a name").package());
+            }
+            Ok(Request::Login(name)) => return Ok(LoginMethod::Guest(name)),
+            Ok(Request::Authenticate(name, password)) => {
+                return Ok(LoginMethod::Password(name, password))
+            }
+            Ok(Request::Register(name, password)) => {
+                return Ok(LoginMethod::Register(name, password))
             }
             Ok(Request::Listen) => {
-                return Ok(None);
+                return Ok(LoginMethod::Listen);
+            }
+            Ok(Request::Hello(args)) => {
+                match connection::Protocol::negotiate(&args, &Server::FEATURES) {
+                    Ok(protocol) => {
+                        conn.send_package(Response::info(protocol.args()).package());
+                        conn.set_protocol(protocol);
+                    }
+                    Err(why) => conn.send_package(Response::err(why).package()),
+                }
             }
+            Err(why) => conn.send_package(why.package()),
             _ => {
                 conn.send_package(Response::err("please login first").package());
             }
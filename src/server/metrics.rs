@@ -0,0 +1,33 @@
+use std::fmt::Display;
+
+/// Statistics about data waiting to be sent to clients
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    /// bytes currently queued, summed over all clients
+    pub queued_bytes: usize,
+    /// most bytes ever queued for a single client
+    pub peak_queued_bytes: usize,
+    /// clients disconnected for not reading fast enough
+    pub slow_disconnects: u64,
+}
+
+impl Metrics {
+    /// update the queue sizes with the current amount queued per client
+    pub fn record_queues(&mut self, queued: impl IntoIterator<Item = usize>) {
+        self.queued_bytes = 0;
+        for bytes in queued {
+            self.queued_bytes += bytes;
+            self.peak_queued_bytes = self.peak_queued_bytes.max(bytes);
+        }
+    }
+}
+
+impl Display for Metrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} bytes queued, at most {} for a single client, {} slow clients disconnected",
+            self.queued_bytes, self.peak_queued_bytes, self.slow_disconnects
+        )
+    }
+}
@@ -0,0 +1,100 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// number of pardons kept for admins to review
+const LOG_LEN: usize = 100;
+
+/// A client reducing the offenses of another one
+struct Pardon {
+    by: String,
+    of: String,
+    /// seconds since the unix epoch (UTC)
+    time: u64,
+}
+
+/// Recent pardons for admins to review, and how often clients pardoned
+///
+/// The log only keeps the last [`LOG_LEN`] pardons. The limit is
+/// counted separately, by the address of the pardoner, or its
+/// name if the address is unknown, so guests can't evade it by
+/// logging in under another name.
+#[derive(Default)]
+pub struct Pardons {
+    /// oldest first
+    log: VecDeque<Pardon>,
+    /// when each key pardoned someone within the window, oldest first
+    recent: HashMap<String, VecDeque<Instant>>,
+}
+
+impl Pardons {
+    /// how often `key` pardoned someone within `window`
+    pub fn count(&self, key: &str, window: Duration) -> usize {
+        self.recent.get(key).map_or(0, |times| {
+            times.iter().filter(|at| at.elapsed() < window).count()
+        })
+    }
+
+    pub fn record(&mut self, by: &str, of: &str) {
+        let time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        self.log.push_back(Pardon {
+            by: by.to_string(),
+            of: of.to_string(),
+            time,
+        });
+        if self.log.len() > LOG_LEN {
+            self.log.pop_front();
+        }
+    }
+
+    /// count a pardon towards the limit of `key`
+    pub fn charge(&mut self, key: &str, window: Duration) {
+        self.recent
+            .entry(key.to_string())
+            .or_default()
+            .push_back(Instant::now());
+        // forget pardons that no longer count, so the map doesn't grow forever
+        self.recent.retain(|_, times| {
+            while times.front().is_some_and(|at| at.elapsed() >= window) {
+                times.pop_front();
+            }
+            !times.is_empty()
+        });
+    }
+
+    /// time, pardoner and pardoned user of each pardon
+    pub fn info_args(&self) -> impl Iterator<Item = String> + '_ {
+        self.log
+            .iter()
+            .flat_map(|p| [p.time.to_string(), p.by.clone(), p.of.clone()])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pardons() {
+        let mut pardons = Pardons::default();
+        let window = Duration::from_secs(60);
+        pardons.charge("127.0.0.1", window);
+        pardons.charge("::1", window);
+        // the same address under another name
+        pardons.charge("127.0.0.1", window);
+        assert_eq!(pardons.count("127.0.0.1", window), 2);
+        assert_eq!(pardons.count("::1", window), 1);
+        assert_eq!(pardons.count("dave", window), 0);
+        assert_eq!(pardons.count("127.0.0.1", Duration::ZERO), 0);
+        pardons.charge("dave", Duration::ZERO);
+        assert!(pardons.recent.is_empty());
+
+        for _ in 0..=LOG_LEN {
+            pardons.record("dave", "bob");
+        }
+        assert_eq!(pardons.info_args().count(), LOG_LEN * 3);
+    }
+}
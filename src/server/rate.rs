@@ -0,0 +1,82 @@
+use std::time::Instant;
+
+use serde::Deserialize;
+
+/// How often a client may make one kind of request
+///
+/// Every client has a bucket holding up to `burst` tokens per kind,
+/// which is refilled by `per_second` tokens each second.
+/// Each request takes a token, requests finding the bucket empty are rejected.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+/// Tokens a client has left for one kind of request
+pub struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// a full bucket
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            tokens: limit.burst as f64,
+            updated: Instant::now(),
+        }
+    }
+
+    /// whether the bucket has been refilled completely, so it can be forgotten
+    pub fn is_full(&self, limit: RateLimit) -> bool {
+        let refill = self.updated.elapsed().as_secs_f64() * limit.per_second;
+        self.tokens + refill >= limit.burst as f64
+    }
+
+    /// take a token, returns `false` if there is none left
+    ///
+    /// The limit is passed on every call, so changes apply to existing buckets.
+    pub fn take(&mut self, limit: RateLimit) -> bool {
+        let now = Instant::now();
+        let refill = (now - self.updated).as_secs_f64() * limit.per_second;
+        self.tokens = (self.tokens + refill).min(limit.burst as f64);
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bucket() {
+        let limit = RateLimit {
+            per_second: 10.0,
+            burst: 2,
+        };
+        let mut bucket = Bucket::new(limit);
+        assert!(bucket.take(limit));
+        assert!(bucket.take(limit));
+        assert!(!bucket.take(limit));
+        std::thread::sleep(std::time::Duration::from_millis(150));
+        assert!(bucket.take(limit));
+        assert!(!bucket.take(limit));
+        assert!(!bucket.is_full(limit));
+        std::thread::sleep(std::time::Duration::from_millis(250));
+        assert!(bucket.is_full(limit));
+
+        let stricter = RateLimit {
+            per_second: 0.0,
+            burst: 0,
+        };
+        assert!(!bucket.take(stricter));
+    }
+}
@@ -0,0 +1,174 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+/// Bans and mutes imposed by admins or for too many offenses
+///
+/// These only last as long as the server runs. They are shared
+/// with the login thread, which turns away banned clients.
+#[derive(Default)]
+pub struct Sanctions {
+    pub banned_names: Expiring<String>,
+    pub banned_addrs: Expiring<IpAddr>,
+    pub muted: Expiring<String>,
+    /// names configured as admins, which aren't affected by bans
+    pub admins: Vec<String>,
+    /// accounts and addresses that may not log in with a password for a while
+    pub locked_names: Expiring<String>,
+    pub locked_addrs: Expiring<IpAddr>,
+    /// failed logins since the last success or lock, by account and address
+    pub(super) failed_names: HashMap<String, usize>,
+    pub(super) failed_addrs: HashMap<IpAddr, usize>,
+}
+
+impl Sanctions {
+    /// whether a client with this name and address may not log in
+    pub fn is_banned(&self, name: Option<&String>, addr: Option<IpAddr>) -> bool {
+        name.is_some_and(|n| self.banned_names.contains(n))
+            || addr.is_some_and(|a| self.banned_addrs.contains(&a))
+    }
+
+    /// whether the login thread should turn away a client right away
+    ///
+    /// Whether a client really is the admin it claims to be is only known
+    /// to the server, so clients using an admin's name are left to it.
+    pub fn turns_away(&self, name: Option<&String>, addr: IpAddr) -> bool {
+        !name.is_some_and(|n| self.admins.contains(n)) && self.is_banned(name, Some(addr))
+    }
+
+    /// whether password logins to `name` or from `addr` are locked
+    pub fn is_locked(&self, name: &String, addr: Option<IpAddr>) -> bool {
+        self.locked_names.contains(name) || addr.is_some_and(|a| self.locked_addrs.contains(&a))
+    }
+
+    /// count a failed login to account `name`, if it exists, from `addr`
+    ///
+    /// After `max` failures in a row, the account or address is locked for `lock`.
+    pub fn fail_login(
+        &mut self,
+        name: Option<&str>,
+        addr: Option<IpAddr>,
+        max: usize,
+        lock: Duration,
+    ) {
+        if let Some(name) = name {
+            count_failure(
+                &mut self.failed_names,
+                &mut self.locked_names,
+                name.to_string(),
+                max,
+                lock,
+            );
+        }
+        if let Some(addr) = addr {
+            count_failure(
+                &mut self.failed_addrs,
+                &mut self.locked_addrs,
+                addr,
+                max,
+                lock,
+            );
+        }
+    }
+
+    /// forget the failed logins to account `name` after it was logged into
+    ///
+    /// Failures of the address are kept, so guessing
+    /// passwords doesn't get easier with an account of one's own.
+    pub fn login_succeeded(&mut self, name: &str) {
+        self.failed_names.remove(name);
+    }
+}
+
+fn count_failure<K: Eq + Hash + Clone>(
+    failed: &mut HashMap<K, usize>,
+    locked: &mut Expiring<K>,
+    key: K,
+    max: usize,
+    lock: Duration,
+) {
+    let count = failed.entry(key.clone()).or_default();
+    *count += 1;
+    if *count >= max {
+        // start over once the lock ends
+        failed.remove(&key);
+        locked.set(key, lock);
+    }
+}
+
+/// A set whose entries end after some time
+pub struct Expiring<K>(HashMap<K, Instant>);
+
+impl<K> Default for Expiring<K> {
+    fn default() -> Self {
+        Self(HashMap::new())
+    }
+}
+
+impl<K: Eq + Hash> Expiring<K> {
+    /// add `key` for `duration`, or remove it if the duration is zero
+    ///
+    /// Returns whether `key` was contained before.
+    pub fn set(&mut self, key: K, duration: Duration) -> bool {
+        let now = Instant::now();
+        self.0.retain(|_, until| *until > now);
+        if duration.is_zero() {
+            self.0.remove(&key).is_some()
+        } else {
+            // durations too long to represent never end
+            let until = now
+                .checked_add(duration)
+                .unwrap_or(now + Duration::from_secs(1 << 40));
+            self.0.insert(key, until).is_some()
+        }
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.0.get(key).is_some_and(|until| *until > Instant::now())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn expiring() {
+        let mut muted = Expiring::default();
+        assert!(!muted.set("bob", Duration::from_millis(20)));
+        assert!(muted.contains(&"bob"));
+        assert!(!muted.contains(&"alice"));
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(!muted.contains(&"bob"));
+        assert!(!muted.set("bob", Duration::ZERO));
+
+        assert!(!muted.set("alice", Duration::from_secs(u64::MAX)));
+        assert!(muted.contains(&"alice"));
+        assert!(muted.set("alice", Duration::ZERO));
+        assert!(!muted.contains(&"alice"));
+    }
+
+    #[test]
+    fn failed_logins() {
+        let mut sanctions = Sanctions::default();
+        let bob = "bob".to_string();
+        let home = IpAddr::from([127, 0, 0, 1]);
+        let away = IpAddr::from([10, 0, 0, 1]);
+        let lock = Duration::from_secs(60);
+        sanctions.fail_login(Some("bob"), Some(home), 2, lock);
+        sanctions.login_succeeded("bob");
+        sanctions.fail_login(Some("bob"), Some(away), 2, lock);
+        assert!(!sanctions.is_locked(&bob, Some(away)));
+        // the address still remembers the failure before the success
+        sanctions.fail_login(None, Some(home), 2, lock);
+        assert!(sanctions.is_locked(&"alice".to_string(), Some(home)));
+        assert!(!sanctions.is_locked(&bob, None));
+        sanctions.fail_login(Some("bob"), None, 2, lock);
+        assert!(sanctions.is_locked(&bob, None));
+        assert!(sanctions.failed_names.is_empty());
+        assert_eq!(sanctions.failed_addrs.len(), 1);
+    }
+}
@@ -0,0 +1,209 @@
+use std::{
+    io::Error,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{self, WebPkiSupportedAlgorithms},
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName, UnixTime},
+    CertificateError, ClientConfig, DigitallySignedStruct, RootCertStore, ServerConfig,
+    SignatureScheme,
+};
+
+/// How a client decides whether to trust a server's certificate
+#[derive(Debug, Clone)]
+pub enum Trust {
+    /// accept certificates issued for the server's name
+    /// by one of the CAs in this PEM file
+    Ca(PathBuf),
+    /// accept exactly the certificate in this PEM file,
+    /// regardless of name and issuer, e.g. a self-signed one
+    Pinned(PathBuf),
+}
+
+/// load a certificate chain and its private key from PEM files
+pub fn server_config(cert: &Path, key: &Path) -> Result<Arc<ServerConfig>, Error> {
+    let chain = load_certs(cert)?;
+    let key = PrivateKeyDer::from_pem_file(key)
+        .map_err(|why| Error::other(format!("failed to read {}: {why}", key.display())))?;
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(chain, key)
+        .map_err(Error::other)?;
+    Ok(Arc::new(config))
+}
+
+/// set up a client to verify servers according to `trust`
+pub fn client_config(trust: &Trust) -> Result<Arc<ClientConfig>, Error> {
+    let config = match trust {
+        Trust::Ca(path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(path)? {
+                roots.add(cert).map_err(Error::other)?;
+            }
+            ClientConfig::builder()
+                .with_root_certificates(roots)
+                .with_no_client_auth()
+        }
+        Trust::Pinned(path) => {
+            let cert = load_certs(path)?.swap_remove(0);
+            ClientConfig::builder()
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(PinnedCert {
+                    cert,
+                    algorithms: crypto::ring::default_provider().signature_verification_algorithms,
+                }))
+                .with_no_client_auth()
+        }
+    };
+    Ok(Arc::new(config))
+}
+
+/// read all certificates in a PEM file, failing if there are none
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, Error> {
+    let failed = |why| Error::other(format!("failed to read {}: {why}", path.display()));
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(failed)?;
+    if certs.is_empty() {
+        return Err(Error::other(format!(
+            "no certificates found in {}",
+            path.display()
+        )));
+    }
+    Ok(certs)
+}
+
+/// Accepts a single known certificate
+///
+/// Signatures made during the handshake are still checked,
+/// so the server has to own the certificate's private key.
+#[derive(Debug)]
+struct PinnedCert {
+    cert: CertificateDer<'static>,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl ServerCertVerifier for PinnedCert {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if *end_entity == self.cert {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, net::TcpListener, thread};
+
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+
+    use super::*;
+    use crate::{connection::Connection, package::Package};
+
+    /// send a package to an echo server, returning either the answer
+    /// or the error that closed the connection
+    fn echo(server: Arc<ServerConfig>, client: Arc<ClientConfig>) -> Result<Package, Error> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let name = ServerName::try_from("localhost").unwrap();
+        let mut conn = Connection::to_tls(listener.local_addr().unwrap(), name, client).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        stream.set_nonblocking(true).unwrap();
+        let stream = mio::net::TcpStream::from_std(stream);
+        let mut peer = Connection::new_tls(stream, server).unwrap();
+        thread::spawn(move || {
+            while let Some(pkg) = peer.wait_package() {
+                peer.send_package(pkg);
+            }
+        });
+        let pkg = Package {
+            cmd: "msg".to_string(),
+            args: vec!["x".repeat(2000)],
+            tag: None,
+        };
+        conn.send_package(&pkg);
+        conn.wait_package()
+            .ok_or_else(|| conn.take_error().expect("connection died without error"))
+    }
+
+    #[test]
+    fn handshake() {
+        let dir = std::env::temp_dir().join(format!("rs_chat_tls_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(Vec::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+        let key = KeyPair::generate().unwrap();
+        let cert = CertificateParams::new(vec!["localhost".to_string()])
+            .unwrap()
+            .signed_by(&key, &ca, &ca_key)
+            .unwrap();
+        let other_key = KeyPair::generate().unwrap();
+        let other = CertificateParams::new(vec!["localhost".to_string()])
+            .unwrap()
+            .self_signed(&other_key)
+            .unwrap();
+        for (file, pem) in [
+            ("ca.pem", ca.pem()),
+            ("cert.pem", cert.pem()),
+            ("key.pem", key.serialize_pem()),
+            ("other.pem", other.pem()),
+        ] {
+            fs::write(dir.join(file), pem).unwrap();
+        }
+
+        let server = server_config(&dir.join("cert.pem"), &dir.join("key.pem")).unwrap();
+        for trust in [
+            Trust::Ca(dir.join("ca.pem")),
+            Trust::Pinned(dir.join("cert.pem")),
+        ] {
+            let pkg = echo(server.clone(), client_config(&trust).unwrap()).unwrap();
+            assert_eq!(pkg.args[0].len(), 2000);
+        }
+        for trust in [
+            Trust::Ca(dir.join("other.pem")),
+            Trust::Pinned(dir.join("other.pem")),
+        ] {
+            let why = echo(server.clone(), client_config(&trust).unwrap()).unwrap_err();
+            assert_eq!(why.kind(), std::io::ErrorKind::InvalidData);
+        }
+        assert!(client_config(&Trust::Ca(dir.join("key.pem"))).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,270 @@
+use std::{
+    collections::VecDeque,
+    io::{Error, ErrorKind, Read, Write},
+    sync::{Arc, Mutex},
+};
+
+use mio::{event::Source, net::TcpStream, Interest, Registry, Token};
+
+/// A nonblocking byte stream a [`Connection`](crate::connection::Connection) can run on
+///
+/// Reads and writes must fail with [`ErrorKind::WouldBlock`] instead of
+/// blocking. Registering with a [`Poll`](mio::Poll) must signal readiness
+/// whenever data arrives, the peer hangs up or a blocked write may succeed.
+pub trait Transport: Read + Write + Source + Send {}
+
+impl Transport for TcpStream {}
+
+#[cfg(unix)]
+impl Transport for mio::net::UnixStream {}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {}
+
+/// bytes a [`MemoryStream`] buffers per direction, like a socket buffer
+const MEMORY_CAPACITY: usize = 64 << 10;
+
+/// One end of an in-memory duplex stream, see [`MemoryStream::pair`]
+///
+/// The data never leaves memory, but a [`Poll`](mio::Poll) can only
+/// wait for OS resources, so registering an end creates a [`Bell`]
+/// the peer rings to signal readiness.
+pub struct MemoryStream {
+    shared: Arc<Mutex<Duplex>>,
+    /// index of this end in `Duplex`
+    side: usize,
+}
+
+/// State shared by both ends, indexed by the reading end
+#[derive(Default)]
+struct Duplex {
+    buffers: [VecDeque<u8>; 2],
+    open: [bool; 2],
+    /// registered ends get notified through these
+    bells: [Option<Bell>; 2],
+}
+
+impl Duplex {
+    fn wake(&mut self, side: usize) {
+        if let Some(bell) = &mut self.bells[side] {
+            bell.ring();
+        }
+    }
+
+    /// reset the bell, so it can be rung again
+    fn silence(&mut self, side: usize) {
+        if let Some(bell) = &mut self.bells[side] {
+            bell.silence();
+        }
+    }
+}
+
+/// A pipe registered with a poll, readable once rung
+#[cfg(unix)]
+struct Bell(mio::unix::pipe::Sender, mio::unix::pipe::Receiver);
+
+#[cfg(unix)]
+impl Bell {
+    fn new(registry: &Registry, token: Token) -> Result<Self, Error> {
+        let (sender, mut receiver) = mio::unix::pipe::new()?;
+        registry.register(&mut receiver, token, Interest::READABLE)?;
+        Ok(Self(sender, receiver))
+    }
+
+    fn ring(&mut self) {
+        // if the pipe is full, it has been rung already
+        let _ = self.0.write(&[0]);
+    }
+
+    fn silence(&mut self) {
+        while self.1.read(&mut [0; 64]).is_ok_and(|bytes| bytes > 0) {}
+    }
+}
+
+/// A [`Waker`](mio::Waker), as there are no pipes to register
+///
+/// A poll only supports a single waker, so only one
+/// memory stream may be registered with each poll.
+#[cfg(not(unix))]
+struct Bell(mio::Waker);
+
+#[cfg(not(unix))]
+impl Bell {
+    fn new(registry: &Registry, token: Token) -> Result<Self, Error> {
+        mio::Waker::new(registry, token).map(Self)
+    }
+
+    fn ring(&mut self) {
+        // only fails if the poll is gone, then nobody is waiting
+        let _ = self.0.wake();
+    }
+
+    fn silence(&mut self) {}
+}
+
+impl MemoryStream {
+    /// create two connected ends
+    pub fn pair() -> (Self, Self) {
+        let shared = Arc::new(Mutex::new(Duplex {
+            open: [true; 2],
+            ..Default::default()
+        }));
+        let end = |side| Self {
+            shared: shared.clone(),
+            side,
+        };
+        (end(0), end(1))
+    }
+
+    fn duplex(&self) -> std::sync::MutexGuard<'_, Duplex> {
+        // the lock is never held while panicking
+        self.shared.lock().expect("memory stream poisoned")
+    }
+
+    fn peer(&self) -> usize {
+        1 - self.side
+    }
+}
+
+impl Transport for MemoryStream {}
+
+impl Read for MemoryStream {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut duplex = self.duplex();
+        duplex.silence(self.side);
+        let incoming = &mut duplex.buffers[self.side];
+        if incoming.is_empty() {
+            if !duplex.open[self.peer()] {
+                return Ok(0);
+            }
+            return Err(ErrorKind::WouldBlock.into());
+        }
+        let was_full = incoming.len() == MEMORY_CAPACITY;
+        let bytes = incoming.read(buf)?;
+        if was_full {
+            duplex.wake(self.peer());
+        }
+        Ok(bytes)
+    }
+}
+
+impl Write for MemoryStream {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        let mut duplex = self.duplex();
+        let peer = self.peer();
+        if !duplex.open[peer] {
+            return Err(ErrorKind::BrokenPipe.into());
+        }
+        let outgoing = &mut duplex.buffers[peer];
+        let bytes = buf.len().min(MEMORY_CAPACITY - outgoing.len());
+        if bytes == 0 && !buf.is_empty() {
+            return Err(ErrorKind::WouldBlock.into());
+        }
+        outgoing.extend(&buf[..bytes]);
+        duplex.wake(peer);
+        Ok(bytes)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl Source for MemoryStream {
+    fn register(&mut self, registry: &Registry, token: Token, _: Interest) -> Result<(), Error> {
+        let bell = Bell::new(registry, token)?;
+        let mut duplex = self.duplex();
+        duplex.bells[self.side] = Some(bell);
+        // data may have arrived before registering
+        if !duplex.buffers[self.side].is_empty() || !duplex.open[self.peer()] {
+            duplex.wake(self.side);
+        }
+        Ok(())
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interest: Interest,
+    ) -> Result<(), Error> {
+        self.register(registry, token, interest)
+    }
+
+    fn deregister(&mut self, _: &Registry) -> Result<(), Error> {
+        // closing the bell removes it from the poll
+        self.duplex().bells[self.side] = None;
+        Ok(())
+    }
+}
+
+impl Drop for MemoryStream {
+    fn drop(&mut self) {
+        let mut duplex = self.duplex();
+        duplex.open[self.side] = false;
+        duplex.bells[self.side] = None;
+        duplex.wake(self.peer());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use mio::{Events, Poll};
+
+    use super::*;
+    use crate::{connection::Connection, package::Package};
+
+    #[test]
+    fn memory() {
+        let (mut a, mut b) = MemoryStream::pair();
+        let mut buf = [0; 16];
+        assert_eq!(b.read(&mut buf).unwrap_err().kind(), ErrorKind::WouldBlock);
+
+        let mut poll = Poll::new().unwrap();
+        let mut events = Events::with_capacity(4);
+        b.register(poll.registry(), Token(1), Interest::READABLE)
+            .unwrap();
+        poll.poll(&mut events, Some(Duration::ZERO)).unwrap();
+        assert!(events.is_empty());
+        assert_eq!(a.write(b"hello").unwrap(), 5);
+        poll.poll(&mut events, Some(Duration::from_secs(1)))
+            .unwrap();
+        assert_eq!(events.iter().next().unwrap().token(), Token(1));
+        assert_eq!(b.read(&mut buf).unwrap(), 5);
+        assert_eq!(&buf[..5], b"hello");
+        // data that arrived before registering is signalled right away
+        assert_eq!(b.write(b"hi").unwrap(), 2);
+        a.register(poll.registry(), Token(2), Interest::READABLE)
+            .unwrap();
+        poll.poll(&mut events, Some(Duration::from_secs(1)))
+            .unwrap();
+        assert_eq!(events.iter().next().unwrap().token(), Token(2));
+        assert_eq!(a.read(&mut buf).unwrap(), 2);
+
+        // full buffers apply backpressure
+        let big = vec![0; MEMORY_CAPACITY + 1];
+        assert_eq!(a.write(&big).unwrap(), MEMORY_CAPACITY);
+        assert_eq!(a.write(&big).unwrap_err().kind(), ErrorKind::WouldBlock);
+        assert_eq!(b.read(&mut buf).unwrap(), buf.len());
+        assert_eq!(a.write(&big).unwrap(), buf.len());
+
+        drop(b);
+        assert_eq!(a.write(b"x").unwrap_err().kind(), ErrorKind::BrokenPipe);
+        assert_eq!(a.read(&mut buf).unwrap(), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix() {
+        let (a, b) = mio::net::UnixStream::pair().unwrap();
+        let (mut a, mut b) = (Connection::new(a), Connection::new(b));
+        a.send_package(Package {
+            cmd: "ping".to_string(),
+            ..Default::default()
+        });
+        assert_eq!(b.wait_package().unwrap().cmd, "ping");
+        drop(a);
+        assert!(b.wait_package().is_none());
+    }
+}
@@ -0,0 +1,848 @@
+//! every documented error case of [`Request`], against a running server
+
+use std::{
+    cell::RefCell,
+    net::SocketAddr,
+    rc::Rc,
+    thread::{self, JoinHandle},
+};
+
+use rs_chat::{
+    Connection, Package, RateLimit, Request, Response, Server, ServerConfig, ServerHandle,
+};
+
+/// a server running in the background until dropped
+struct TestServer {
+    addr: SocketAddr,
+    handle: ServerHandle,
+    thread: Option<JoinHandle<()>>,
+    /// pardons every error, as clients are kicked after a few offenses
+    ///
+    /// As an admin, its pardons aren't limited.
+    judge: Rc<RefCell<Client>>,
+}
+
+impl TestServer {
+    fn start() -> Self {
+        Self::with_config(ServerConfig::default())
+    }
+
+    fn with_config(mut config: ServerConfig) -> Self {
+        config.admins.push("judge".to_string());
+        let mut server = Server::builder()
+            .config(ServerConfig {
+                accounts: None,
+                mailbox_len: 1,
+                max_msg_len: 10,
+                ..config
+            })
+            .bind(([127, 0, 0, 1], 0))
+            .build()
+            .expect("failed to start server");
+        let addr = server.addrs()[0];
+        let handle = server.handle();
+        let thread = thread::spawn(move || server.run());
+        let mut judge = Client {
+            conn: Connection::to(addr).expect("failed to connect"),
+            name: None,
+            judge: None,
+        };
+        judge.ack(Request::register("judge", "secret"));
+        Self {
+            addr,
+            handle,
+            thread: Some(thread),
+            judge: Rc::new(RefCell::new(judge)),
+        }
+    }
+
+    fn connect(&self) -> Client {
+        Client {
+            conn: Connection::to(self.addr).expect("failed to connect"),
+            name: None,
+            judge: Some(self.judge.clone()),
+        }
+    }
+
+    /// connect and log in as a guest
+    fn login(&self, name: &str) -> Client {
+        let mut client = self.connect();
+        client.ack(Request::login(name));
+        client.name = Some(name.to_string());
+        client
+    }
+
+    /// connect and log in with a new account
+    fn register(&self, name: &str) -> Client {
+        let mut client = self.connect();
+        client.ack(Request::register(name, "secret"));
+        client.name = Some(name.to_string());
+        client
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.handle.stop();
+        if let Some(thread) = self.thread.take() {
+            // don't panic twice if a test failed
+            let _ = thread.join();
+        }
+    }
+}
+
+struct Client {
+    conn: Connection,
+    /// set once logged in
+    name: Option<String>,
+    judge: Option<Rc<RefCell<Client>>>,
+}
+
+impl Client {
+    /// send a request and wait for its response, skipping async ones
+    fn request(&mut self, req: impl Into<Package>) -> Response {
+        self.conn.send_package(req.into());
+        loop {
+            let pkg = self
+                .conn
+                .wait_package()
+                .expect("server closed the connection");
+            if !Response::ASYNC.contains(&pkg.cmd.as_str()) {
+                return Response::try_from(pkg).expect("invalid response");
+            }
+        }
+    }
+
+    fn ack(&mut self, req: Request) {
+        let resp = self.request(req);
+        assert!(matches!(resp, Response::Ack), "expected ack, got {resp:?}");
+    }
+
+    fn info(&mut self, req: impl Into<Package>) -> Vec<String> {
+        match self.request(req) {
+            Response::Info(info) => info,
+            resp => panic!("expected info, got {resp:?}"),
+        }
+    }
+
+    fn err(&mut self, req: impl Into<Package>, why: &str) {
+        match self.request(req) {
+            Response::Err(err) => assert_eq!(err, why),
+            resp => panic!("expected error '{why}', got {resp:?}"),
+        }
+        if let (Some(name), Some(judge)) = (&self.name, &self.judge) {
+            judge.borrow_mut().ack(Request::pardon(name));
+        }
+    }
+
+    /// the error the server disconnected the client with
+    fn closed_with(&mut self) -> String {
+        loop {
+            let pkg = self.conn.wait_package().expect("no reason given");
+            if Response::ASYNC.contains(&pkg.cmd.as_str()) {
+                continue;
+            }
+            let Ok(Response::Err(why)) = Response::try_from(pkg) else {
+                panic!("expected error");
+            };
+            assert!(self.conn.wait_package().is_none());
+            return why;
+        }
+    }
+}
+
+/// wait until the server has noticed that `name` left
+fn wait_for_leave(client: &mut Client, name: &str) {
+    while client
+        .info(Request::names(""))
+        .iter()
+        .any(|member| member == name)
+    {
+        thread::yield_now();
+    }
+}
+
+#[test]
+fn hello() {
+    let server = TestServer::start();
+    let mut client = server.connect();
+    client.err(
+        Request::Hello(Vec::new()),
+        "insufficient args provided, expected [version]",
+    );
+    client.err(Request::hello(["two"]), "invalid protocol version two");
+    client.err(
+        Request::hello(["0"]),
+        "unsupported protocol version 0, expected 1 to 2",
+    );
+    assert_eq!(client.info(Request::hello(["2"]))[0], "2");
+    client.ack(Request::login("alice"));
+    client.err(Request::hello(["2"]), "already logged in");
+}
+
+#[test]
+fn login() {
+    let server = TestServer::start();
+    let _bob = server.register("bob");
+    let mut alice = server.login("alice");
+    alice.err(Request::login("alice"), "already logged in");
+    server.connect().err(Request::login("a b"), "invalid name");
+    server
+        .connect()
+        .err(Request::login(""), "please provide a name");
+    server
+        .connect()
+        .err(Request::login("bob"), "name is registered");
+    server
+        .connect()
+        .err(Request::login("alice"), "name already used");
+    server
+        .connect()
+        .err(Request::login("default_bot"), "name already used");
+
+    let server = TestServer::with_config(ServerConfig {
+        guests: false,
+        ..Default::default()
+    });
+    server
+        .connect()
+        .err(Request::login("alice"), "guest logins are disabled");
+}
+
+#[test]
+fn authenticate() {
+    let server = TestServer::start();
+    let mut bob = server.register("bob");
+    bob.err(Request::authenticate("bob", "secret"), "already logged in");
+    server
+        .connect()
+        .err(Request::authenticate("a b", "secret"), "invalid name");
+    server.connect().err(
+        Request::authenticate("bob", "wrong"),
+        "wrong name or password",
+    );
+    server.connect().err(
+        Request::authenticate("carol", "secret"),
+        "wrong name or password",
+    );
+    server
+        .connect()
+        .err(Request::authenticate("bob", "secret"), "name already used");
+}
+
+#[test]
+fn failed_logins() {
+    let server = TestServer::with_config(ServerConfig {
+        max_failed_logins: 2,
+        ..Default::default()
+    });
+    drop(server.register("bob"));
+    wait_for_leave(&mut server.login("carol"), "bob");
+    for _ in 0..2 {
+        server.connect().err(
+            Request::authenticate("bob", "wrong"),
+            "wrong name or password",
+        );
+    }
+    server.connect().err(
+        Request::authenticate("bob", "secret"),
+        "too many failed logins, try again later",
+    );
+    // only password logins are locked
+    server.register("dave");
+}
+
+#[test]
+fn register() {
+    let server = TestServer::start();
+    let mut bob = server.register("bob");
+    let _alice = server.login("alice");
+    bob.err(Request::register("carol", "secret"), "already logged in");
+    server
+        .connect()
+        .err(Request::register("a b", "secret"), "invalid name");
+    server
+        .connect()
+        .err(Request::register("bob", "secret"), "name already used");
+    server
+        .connect()
+        .err(Request::register("alice", "secret"), "name already used");
+    drop(bob);
+    wait_for_leave(&mut server.login("carol"), "bob");
+    server.connect().err(
+        Request::register("bob", "secret"),
+        "name is already registered",
+    );
+}
+
+#[test]
+fn post() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice");
+    alice.err(Request::post("a b", "hi"), "invalid name");
+    alice.err(Request::post("nowhere", "hi"), "channel doesn't exist");
+    alice.err(Request::post("printf", "hi"), "not subscribed to channel");
+    alice.err(
+        Request::post("", "hello world"),
+        "message exceeds 10 characters",
+    );
+    alice.ack(Request::post("", "hi"));
+}
+
+#[test]
+fn send() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice");
+    let mut bob = server.register("bob");
+    alice.err(Request::send("a b", "hi"), "invalid name");
+    alice.err(Request::send("carol", "hi"), "user doesn't exist");
+    alice.err(
+        Request::send("default_bot", "hi"),
+        "bots can't receive messages",
+    );
+    alice.err(
+        Request::send("bob", "hello world"),
+        "message exceeds 10 characters",
+    );
+    alice.ack(Request::send("bob", "hi"));
+
+    bob.ack(Request::block("alice"));
+    alice.err(Request::send("bob", "hi"), "you were blocked by user");
+    bob.err(Request::send("alice", "hi"), "user was blocked");
+    bob.ack(Request::unblock("alice"));
+
+    // offline users get their messages later, up to `mailbox_len`
+    drop(bob);
+    wait_for_leave(&mut alice, "bob");
+    alice.ack(Request::send("bob", "hi"));
+    alice.err(Request::send("bob", "hi"), "user's mailbox is full");
+}
+
+#[test]
+fn names_and_history() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice");
+    alice.err(Request::names("a b"), "invalid name");
+    alice.err(Request::names("nowhere"), "channel doesn't exist");
+    alice.err(Request::names("printf"), "not subscribed to channel");
+    alice.err(Request::history("a b", "1"), "invalid name");
+    alice.err(Request::history("", "many"), "invalid count");
+    alice.err(Request::history("nowhere", "1"), "channel doesn't exist");
+    alice.err(Request::history("printf", "1"), "not subscribed to channel");
+}
+
+#[test]
+fn new_channel() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice");
+    alice.err(Request::new_channel("a b", ""), "invalid name");
+    alice.err(Request::new_channel("printf", ""), "channel exists already");
+    alice.err(
+        Request::new_channel("__direct", ""),
+        "channel exists already",
+    );
+}
+
+#[test]
+fn topic() {
+    let server = admin_server();
+    let mut alice = server.login("alice");
+    let mut bob = server.login("bob");
+    let mut root = server.register("root");
+    alice.ack(Request::new_channel("rust", ""));
+    let topic = |args: &[&str]| Package {
+        cmd: "topic".to_string(),
+        args: args.iter().map(|a| a.to_string()).collect(),
+        tag: None,
+    };
+    alice.err(topic(&[]), "insufficient args provided, expected [channel]");
+    // extra args are ignored, so this doesn't set the topic
+    assert_eq!(alice.info(topic(&["rust", "crabs"])), [""]);
+    alice.err(Request::topic("a b"), "invalid name");
+    alice.err(Request::topic("nowhere"), "channel doesn't exist");
+    bob.err(
+        Request::set_topic("rust", "crabs"),
+        "not subscribed to channel",
+    );
+    bob.ack(Request::subscribe("rust", ""));
+    bob.err(
+        Request::set_topic("rust", "crabs"),
+        "not an operator of channel",
+    );
+    alice.err(
+        Request::set_topic("rust", "crabs everywhere"),
+        "message exceeds 10 characters",
+    );
+    alice.ack(Request::set_topic("rust", "crabs"));
+    assert_eq!(bob.info(Request::topic("rust")), ["crabs"]);
+
+    // nobody operates the global channel, so only admins may set its topic
+    alice.err(Request::set_topic("", "hi"), "not an operator of channel");
+    root.ack(Request::set_topic("", "hi"));
+    // clients speaking the legacy protocol only get the names
+    assert_eq!(alice.info(Request::ListChannels).len(), 3);
+    let mut carol = server.connect();
+    assert_eq!(carol.info(Request::hello(["2"]))[0], "2");
+    carol.ack(Request::login("carol"));
+    let mut channels: Vec<_> = carol
+        .info(Request::ListChannels)
+        .chunks(2)
+        .map(<[String]>::to_vec)
+        .collect();
+    channels.sort();
+    assert_eq!(
+        channels,
+        [["", "hi"], ["printf", ""], ["rust", "crabs"]].map(|c| c.map(String::from))
+    );
+}
+
+#[test]
+fn subscribe() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice");
+    let mut bob = server.login("bob");
+    alice.ack(Request::new_channel("rust", "crab"));
+    alice.ack(Request::ban("rust", "bob"));
+    alice.err(Request::subscribe("a b", ""), "invalid name");
+    alice.err(Request::subscribe("nowhere", ""), "channel doesn't exist");
+    alice.err(
+        Request::subscribe("rust", "crab"),
+        "already subscribed to channel",
+    );
+    bob.err(Request::subscribe("rust", "crab"), "banned from channel");
+    let mut carol = server.login("carol");
+    carol.err(Request::subscribe("rust", "gopher"), "wrong password");
+    carol.ack(Request::subscribe("rust", "crab"));
+}
+
+#[test]
+fn unsubscribe() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice");
+    alice.err(Request::unsubscribe("a b"), "invalid name");
+    alice.err(Request::unsubscribe("nowhere"), "channel doesn't exist");
+    alice.err(Request::unsubscribe("printf"), "not subscribed to channel");
+    alice.ack(Request::unsubscribe(""));
+}
+
+/// `alice` founds `rust`, `bob` is an operator and `carol` a member
+fn moderated_channel(server: &TestServer) -> (Client, Client, Client) {
+    let mut alice = server.login("alice");
+    let mut bob = server.login("bob");
+    let mut carol = server.login("carol");
+    alice.ack(Request::new_channel("rust", ""));
+    bob.ack(Request::subscribe("rust", ""));
+    carol.ack(Request::subscribe("rust", ""));
+    alice.ack(Request::promote("rust", "bob"));
+    (alice, bob, carol)
+}
+
+#[test]
+fn kick() {
+    let server = TestServer::start();
+    let (mut alice, mut bob, mut carol) = moderated_channel(&server);
+    let mut dave = server.login("dave");
+    bob.err(Request::kick("a b", "carol"), "invalid name");
+    bob.err(Request::kick("rust", "a b"), "invalid name");
+    bob.err(Request::kick("nowhere", "carol"), "channel doesn't exist");
+    dave.err(Request::kick("rust", "carol"), "not subscribed to channel");
+    carol.err(Request::kick("rust", "bob"), "not an operator of channel");
+    bob.err(
+        Request::kick("rust", "dave"),
+        "user is not subscribed to channel",
+    );
+    bob.err(Request::kick("rust", "alice"), "insufficient permissions");
+    alice.ack(Request::kick("rust", "bob"));
+}
+
+#[test]
+fn ban_and_unban() {
+    let server = TestServer::start();
+    let (mut alice, mut bob, mut carol) = moderated_channel(&server);
+    let mut dave = server.login("dave");
+    bob.err(Request::ban("a b", "carol"), "invalid name");
+    bob.err(Request::ban("rust", "a b"), "invalid name");
+    bob.err(Request::ban("nowhere", "carol"), "channel doesn't exist");
+    dave.err(Request::ban("rust", "carol"), "not subscribed to channel");
+    carol.err(Request::ban("rust", "dave"), "not an operator of channel");
+    bob.err(Request::ban("rust", "alice"), "insufficient permissions");
+    bob.ack(Request::ban("rust", "dave"));
+    bob.err(Request::ban("rust", "dave"), "user is already banned");
+
+    bob.err(Request::unban("a b", "dave"), "invalid name");
+    bob.err(Request::unban("rust", "a b"), "invalid name");
+    bob.err(Request::unban("nowhere", "dave"), "channel doesn't exist");
+    dave.err(Request::unban("rust", "dave"), "not subscribed to channel");
+    carol.err(Request::unban("rust", "dave"), "not an operator of channel");
+    alice.ack(Request::unban("rust", "dave"));
+    bob.err(Request::unban("rust", "dave"), "user is not banned");
+}
+
+#[test]
+fn promote_and_demote() {
+    let server = TestServer::start();
+    let (mut alice, mut bob, mut carol) = moderated_channel(&server);
+    let mut dave = server.login("dave");
+    bob.err(Request::promote("a b", "carol"), "invalid name");
+    bob.err(Request::promote("rust", "a b"), "invalid name");
+    bob.err(
+        Request::promote("nowhere", "carol"),
+        "channel doesn't exist",
+    );
+    dave.err(
+        Request::promote("rust", "carol"),
+        "not subscribed to channel",
+    );
+    carol.err(
+        Request::promote("rust", "carol"),
+        "not an operator of channel",
+    );
+    bob.err(
+        Request::promote("rust", "dave"),
+        "user is not subscribed to channel",
+    );
+    bob.err(
+        Request::promote("rust", "bob"),
+        "user is already an operator",
+    );
+    bob.err(
+        Request::promote("rust", "alice"),
+        "user is already an operator",
+    );
+
+    alice.err(Request::demote("a b", "bob"), "invalid name");
+    alice.err(Request::demote("rust", "a b"), "invalid name");
+    alice.err(Request::demote("nowhere", "bob"), "channel doesn't exist");
+    dave.err(Request::demote("rust", "bob"), "not subscribed to channel");
+    bob.err(Request::demote("rust", "bob"), "not the founder of channel");
+    alice.err(Request::demote("rust", "carol"), "user is not an operator");
+    alice.ack(Request::demote("rust", "bob"));
+}
+
+#[test]
+fn handover() {
+    let server = TestServer::start();
+    let (mut alice, mut bob, mut carol) = moderated_channel(&server);
+    alice.err(Request::handover("a b", "bob"), "invalid name");
+    alice.err(Request::handover("rust", "a b"), "invalid name");
+    alice.err(Request::handover("nowhere", "bob"), "channel doesn't exist");
+    bob.err(
+        Request::handover("rust", "carol"),
+        "not the founder of channel",
+    );
+    alice.err(
+        Request::handover("rust", "dave"),
+        "user is not subscribed to channel",
+    );
+    alice.err(
+        Request::handover("rust", "alice"),
+        "user is already the founder",
+    );
+    alice.ack(Request::handover("rust", "carol"));
+    alice.err(Request::demote("rust", "bob"), "not the founder of channel");
+    carol.ack(Request::demote("rust", "alice"));
+
+    // guests lose the role when leaving, so their name can't take it over
+    drop(carol);
+    wait_for_leave(&mut alice, "carol");
+    let mut carol = server.login("carol");
+    carol.ack(Request::subscribe("rust", ""));
+    carol.err(Request::demote("rust", "bob"), "not the founder of channel");
+
+    // founders with an account keep it
+    let mut dave = server.register("dave");
+    dave.ack(Request::new_channel("crab", ""));
+    bob.ack(Request::subscribe("crab", ""));
+    carol.ack(Request::subscribe("crab", ""));
+    dave.ack(Request::promote("crab", "bob"));
+    dave.ack(Request::unsubscribe("crab"));
+    // even if a guest leaves while they are away
+    carol.ack(Request::unsubscribe("crab"));
+    dave.ack(Request::subscribe("crab", ""));
+    dave.ack(Request::demote("crab", "bob"));
+}
+
+#[test]
+fn channel_password() {
+    let server = TestServer::start();
+    let (_alice, mut bob, mut carol) = moderated_channel(&server);
+    let mut dave = server.login("dave");
+    bob.err(Request::channel_password("a b", "crab"), "invalid name");
+    bob.err(
+        Request::channel_password("nowhere", "crab"),
+        "channel doesn't exist",
+    );
+    dave.err(
+        Request::channel_password("rust", "crab"),
+        "not subscribed to channel",
+    );
+    carol.err(
+        Request::channel_password("rust", "crab"),
+        "not an operator of channel",
+    );
+    bob.ack(Request::channel_password("rust", "crab"));
+}
+
+#[test]
+fn slow_mode() {
+    let server = TestServer::start();
+    let (mut alice, mut bob, mut carol) = moderated_channel(&server);
+    let mut dave = server.login("dave");
+    bob.err(Request::slow_mode("a b", "60"), "invalid name");
+    bob.err(Request::slow_mode("nowhere", "60"), "channel doesn't exist");
+    dave.err(
+        Request::slow_mode("rust", "60"),
+        "not subscribed to channel",
+    );
+    carol.err(
+        Request::slow_mode("rust", "60"),
+        "not an operator of channel",
+    );
+    bob.err(Request::slow_mode("rust", "soon"), "invalid duration");
+    bob.ack(Request::slow_mode("rust", "60"));
+    carol.ack(Request::post("rust", "hi"));
+    carol.err(Request::post("rust", "hi"), "slow mode, wait 60 seconds");
+    carol.ack(Request::post("", "hi"));
+    alice.ack(Request::post("rust", "hi"));
+    alice.ack(Request::post("rust", "hi"));
+    bob.ack(Request::slow_mode("rust", "0"));
+    carol.ack(Request::post("rust", "hi"));
+}
+
+#[test]
+fn block_and_unblock() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice");
+    let _bob = server.login("bob");
+    alice.err(Request::block("a b"), "invalid name");
+    alice.err(Request::block("carol"), "user doesn't exist");
+    alice.ack(Request::block("bob"));
+    alice.err(Request::block("bob"), "user already blocked");
+
+    alice.err(Request::unblock("a b"), "invalid name");
+    alice.ack(Request::unblock("bob"));
+    alice.err(Request::unblock("bob"), "user wasn't blocked");
+}
+
+#[test]
+fn pardon() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice");
+    let mut bob = server.login("bob");
+    alice.err(Request::pardon("a b"), "invalid name");
+    alice.err(Request::pardon("carol"), "user doesn't exist");
+    alice.err(Request::pardon("alice"), "can't pardon yourself");
+    // without the judge stepping in, errors are offenses
+    assert!(matches!(
+        alice.request(Request::pardon("bob")),
+        Response::Err(_)
+    ));
+    assert_eq!(alice.info(Request::Offenses)[0], "1");
+    bob.ack(Request::pardon("alice"));
+    bob.err(Request::pardon("alice"), "user has no offenses");
+    assert_eq!(alice.info(Request::Offenses)[0], "0");
+}
+
+#[test]
+fn offense_ban() {
+    let server = TestServer::with_config(ServerConfig {
+        admins: vec!["root".to_string()],
+        max_offenses: 2,
+        offense_ban_secs: 60,
+        ..Default::default()
+    });
+    let mut alice = server.login("alice");
+    let mut bob = server.login("bob");
+    assert_eq!(alice.info(Request::Offenses), ["0", "2"]);
+    for _ in 0..2 {
+        assert!(matches!(
+            alice.request(Request::pardon("carol")),
+            Response::Err(_)
+        ));
+    }
+    assert!(alice.conn.wait_package().is_none());
+    wait_for_leave(&mut bob, "alice");
+    // the test clients all connect from the same address
+    server
+        .connect()
+        .err(Request::login("alice"), "banned from server");
+    server
+        .connect()
+        .err(Request::login("carol"), "banned from server");
+    server.connect().err(Request::Listen, "banned from server");
+    // admins are never banned
+    server.register("root");
+}
+
+#[test]
+fn pardon_limit() {
+    let server = TestServer::with_config(ServerConfig {
+        admins: vec!["root".to_string()],
+        max_pardons: 1,
+        ..Default::default()
+    });
+    let mut root = server.register("root");
+    let mut alice = server.login("alice");
+    let mut bob = server.login("bob");
+    for _ in 0..4 {
+        assert!(matches!(
+            alice.request(Request::pardon("alice")),
+            Response::Err(_)
+        ));
+    }
+    bob.ack(Request::pardon("alice"));
+    bob.err(
+        Request::pardon("alice"),
+        "too many pardons, try again later",
+    );
+    // the limit applies to the address, whatever the name
+    server.login("carol").err(
+        Request::pardon("alice"),
+        "too many pardons, try again later",
+    );
+    // registered users have a limit of their own
+    let mut dave = server.register("dave");
+    dave.ack(Request::pardon("alice"));
+    dave.err(
+        Request::pardon("alice"),
+        "too many pardons, try again later",
+    );
+    // admins aren't limited
+    root.ack(Request::pardon("alice"));
+    root.ack(Request::pardon("alice"));
+    alice.err(Request::PardonLog, "not an admin");
+    let log = root.info(Request::PardonLog);
+    let pardons: Vec<_> = log.chunks(3).map(|p| (&*p[1], &*p[2])).collect();
+    assert_eq!(
+        pardons,
+        [
+            ("bob", "alice"),
+            ("judge", "bob"),
+            ("judge", "carol"),
+            ("dave", "alice"),
+            ("judge", "dave"),
+            ("root", "alice"),
+            ("root", "alice"),
+            ("judge", "alice"),
+        ]
+    );
+}
+
+/// `root` is an admin, once registered
+fn admin_server() -> TestServer {
+    TestServer::with_config(ServerConfig {
+        admins: vec!["root".to_string(), "nobody".to_string()],
+        ..Default::default()
+    })
+}
+
+#[test]
+fn server_kick() {
+    let server = admin_server();
+    let mut root = server.register("root");
+    let mut alice = server.login("alice");
+    alice.err(Request::server_kick("a b"), "invalid name");
+    alice.err(Request::server_kick("root"), "not an admin");
+    // without an account, anyone could use the name
+    let mut nobody = server.login("nobody");
+    nobody.err(Request::server_kick("alice"), "not an admin");
+    root.err(Request::server_kick("bob"), "user is not online");
+    root.err(Request::server_kick("root"), "user is an admin");
+    root.ack(Request::server_kick("alice"));
+    assert_eq!(alice.closed_with(), "kicked by admin");
+    server.login("alice");
+}
+
+#[test]
+fn server_ban() {
+    let server = admin_server();
+    let mut root = server.register("root");
+    let mut alice = server.login("alice");
+    alice.err(Request::server_ban("a b", "60"), "invalid name");
+    alice.err(Request::server_ban("bob", "60"), "not an admin");
+    root.err(Request::server_ban("bob", "soon"), "invalid duration");
+    root.err(Request::server_ban("root", "60"), "user is an admin");
+    root.err(Request::server_ban("bob", "0"), "user is not banned");
+    root.err(
+        Request::server_ban("10.0.0.1", "0"),
+        "address is not banned",
+    );
+    root.ack(Request::server_ban("alice", "60"));
+    assert_eq!(alice.closed_with(), "banned from server");
+    server
+        .connect()
+        .err(Request::login("alice"), "banned from server");
+    root.ack(Request::server_ban("alice", "0"));
+    server.login("alice");
+
+    // everyone connecting from the address is affected, except admins
+    let mut bob = server.login("bob");
+    root.ack(Request::server_ban("127.0.0.1", "60"));
+    assert_eq!(bob.closed_with(), "banned from server");
+    server
+        .connect()
+        .err(Request::login("carol"), "banned from server");
+    server.connect().err(Request::Listen, "banned from server");
+    root.ack(Request::server_ban("127.0.0.1", "0"));
+    server.login("carol");
+}
+
+#[test]
+fn mute() {
+    let server = admin_server();
+    let mut root = server.register("root");
+    let mut alice = server.login("alice");
+    alice.err(Request::mute("a b", "60"), "invalid name");
+    alice.err(Request::mute("root", "60"), "not an admin");
+    root.err(Request::mute("alice", "soon"), "invalid duration");
+    root.err(Request::mute("bob", "60"), "user doesn't exist");
+    root.err(Request::mute("root", "60"), "user is an admin");
+    root.err(Request::mute("alice", "0"), "user is not muted");
+    root.ack(Request::mute("alice", "60"));
+    alice.err(Request::post("", "hi"), "you are muted");
+    alice.err(Request::send("root", "hi"), "you are muted");
+    alice.ack(Request::new_channel("rust", ""));
+    alice.err(Request::set_topic("rust", "crabs"), "you are muted");
+    root.ack(Request::mute("alice", "0"));
+    alice.ack(Request::post("", "hi"));
+}
+
+#[test]
+fn rate_limit() {
+    let limit = RateLimit {
+        per_second: 0.0,
+        burst: 2,
+    };
+    let server = TestServer::with_config(ServerConfig {
+        rate_limits: [("ping".to_string(), limit)].into(),
+        ..Default::default()
+    });
+    let mut alice = server.login("alice");
+    alice.ack(Request::Ping);
+    alice.ack(Request::Ping);
+    alice.err(Request::Ping, "rate limited");
+    alice.info(Request::About);
+    // rejected requests are offenses as well
+    assert!(matches!(alice.request(Request::Ping), Response::Err(_)));
+    assert_eq!(alice.info(Request::Offenses)[0], "1");
+}
+
+#[test]
+fn login_rate_limit() {
+    let limit = RateLimit {
+        per_second: 0.0,
+        burst: 3,
+    };
+    let server = TestServer::with_config(ServerConfig {
+        rate_limits: [("register".to_string(), limit)].into(),
+        ..Default::default()
+    });
+    // the judge took the first token
+    server.register("bob");
+    server.register("carol");
+    // the test clients all connect from the same address
+    server
+        .connect()
+        .err(Request::register("dave", "secret"), "rate limited");
+    server.login("dave");
+}